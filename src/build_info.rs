@@ -0,0 +1,47 @@
+use crate::parser::perekrestok::Perekrestok;
+use crate::parser::pyaterochka::Pyaterochka;
+use crate::parser::retailer::{CatalogFilter, CatalogPage, Retailer};
+use std::fmt::Write;
+
+/// Crate version and the endpoints and catalog ids this build assumes, to compare with the
+/// site when the API changes.
+pub fn build_info() -> String {
+    let mut info = format!("{} {}\n", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    retailer_info(&mut info, &Pyaterochka);
+    retailer_info(&mut info, &Perekrestok);
+
+    info
+}
+
+fn retailer_info<R: Retailer>(info: &mut String, retailer: &R) {
+    let catalogs = retailer.catalogs(None);
+    let _ = writeln!(info, "\n{}", retailer.name());
+    let _ = writeln!(info, "  home page: {}", retailer.home_page_url());
+    let _ = writeln!(
+        info,
+        "  store lookup: {}",
+        retailer.store_lookup_url(55.75, 37.62)
+    );
+    if let Some(&c) = catalogs.first() {
+        let page = CatalogPage {
+            limit: retailer.max_catalog_limit(),
+            offset: 0,
+            filter: CatalogFilter::Default,
+        };
+        let _ = writeln!(
+            info,
+            "  catalog page: {}",
+            retailer.catalog_url("{store_id}", c, page)
+        );
+    }
+    let _ = writeln!(info, "  catalogs:");
+    for c in catalogs {
+        let name = format!("{c:?}");
+        let id = retailer.catalog_id(c);
+        let _ = writeln!(
+            info,
+            "    {id:<9}  {name:<28}  {}",
+            retailer.catalog_name(c)
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use crate::parser::{perekrestok, pyaterochka};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Config {
+    pub db_path: Option<String>,
+    pub db_wal: Option<bool>,
+    pub db_busy_timeout_ms: Option<u64>,
+    pub table_prefix: Option<String>,
+    pub browser_executable: Option<String>,
+    pub cookies_store_path: Option<String>,
+    pub pyaterochka_stores_coord_path: Option<String>,
+    pub sleep_millis_for_each_catalog: Option<u64>,
+    pub cookie_refresh_headless: Option<bool>,
+    pub catalog_limit: Option<u16>,
+    pub proxy: Option<String>,
+    pub proxies: Option<Vec<String>>,
+    pub max_concurrent_catalogs: Option<usize>,
+    pub sleep_jitter_millis: Option<u64>,
+    pub min_store_revisit_secs: Option<u64>,
+    pub catalogs: Option<Vec<String>>,
+    pub dry_run: Option<bool>,
+    pub cookie_refresh_threshold: Option<u32>,
+    pub user_agent: Option<String>,
+    pub user_agents: Option<Vec<String>>,
+    pub store_source: Option<String>,
+    pub catalog_filter: Option<String>,
+    pub cookie_wait_timeout_secs: Option<u64>,
+    pub challenge_markers: Option<Vec<String>>,
+    pub run_once: Option<bool>,
+    pub max_loops: Option<u64>,
+    pub max_runtime_secs: Option<u64>,
+    pub download_images: Option<bool>,
+    pub image_store_path: Option<String>,
+    pub max_store_distance_km: Option<f64>,
+    pub status_port: Option<u16>,
+    pub metrics_port: Option<u16>,
+    pub json_wait_selector: Option<String>,
+    pub fetch_mode: Option<String>,
+    pub retailer: Option<String>,
+    pub vacuum_every_loops: Option<u64>,
+    pub history_retention_days: Option<u64>,
+    pub min_catalog_products: Option<usize>,
+    pub max_empty_products_ratio: Option<f64>,
+    pub window_width: Option<u32>,
+    pub window_height: Option<u32>,
+    pub extra_browser_args: Option<Vec<String>>,
+    pub catalog_timeout_secs: Option<u64>,
+    pub shuffle_stores: Option<bool>,
+    pub resume: Option<bool>,
+    pub max_requests_per_minute: Option<u32>,
+    pub cookie_min_validity_secs: Option<u64>,
+    pub json_output_dir: Option<String>,
+    pub compress_output: Option<bool>,
+    pub block_backoff_base_secs: Option<u64>,
+    pub block_backoff_max_secs: Option<u64>,
+    pub cookie_wait_selector: Option<String>,
+    pub cookie_page_timeout_secs: Option<u64>,
+    pub geocode_mode: Option<String>,
+    pub price_policy: Option<String>,
+    pub store_lookup_retries: Option<u32>,
+    pub raw_response_dir: Option<String>,
+    pub restart_browser_every_stores: Option<usize>,
+    pub status_bind_address: Option<String>,
+    pub metrics_bind_address: Option<String>,
+}
+
+fn env_value(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+fn set_string(field: &mut Option<String>, name: &str) {
+    if let Some(v) = env_value(name) {
+        *field = Some(v);
+    }
+}
+
+fn set_parsed<T: FromStr>(field: &mut Option<T>, name: &str) {
+    if let Some(v) = env_value(name) {
+        match v.trim().parse::<T>() {
+            Ok(v) => *field = Some(v),
+            Err(_) => warn!("Ignoring invalid {name}={v:?}"),
+        }
+    }
+}
+
+fn set_list(field: &mut Option<Vec<String>>, name: &str) {
+    if let Some(v) = env_value(name) {
+        *field = Some(
+            v.split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect(),
+        );
+    }
+}
+
+impl Config {
+    /// Config built only from `X5_*` environment variables.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        config.apply_env();
+        config
+    }
+
+    /// Overrides fields with `X5_<FIELD_NAME>` environment variables that are set.
+    /// List fields are comma separated.
+    pub fn apply_env(&mut self) {
+        set_string(&mut self.db_path, "X5_DB_PATH");
+        set_parsed(&mut self.db_wal, "X5_DB_WAL");
+        set_parsed(&mut self.db_busy_timeout_ms, "X5_DB_BUSY_TIMEOUT_MS");
+        set_string(&mut self.table_prefix, "X5_TABLE_PREFIX");
+        set_string(&mut self.browser_executable, "X5_BROWSER_EXECUTABLE");
+        set_string(&mut self.cookies_store_path, "X5_COOKIES_STORE_PATH");
+        set_string(
+            &mut self.pyaterochka_stores_coord_path,
+            "X5_PYATEROCHKA_STORES_COORD_PATH",
+        );
+        set_parsed(
+            &mut self.sleep_millis_for_each_catalog,
+            "X5_SLEEP_MILLIS_FOR_EACH_CATALOG",
+        );
+        set_parsed(
+            &mut self.cookie_refresh_headless,
+            "X5_COOKIE_REFRESH_HEADLESS",
+        );
+        set_parsed(&mut self.catalog_limit, "X5_CATALOG_LIMIT");
+        set_string(&mut self.proxy, "X5_PROXY");
+        set_list(&mut self.proxies, "X5_PROXIES");
+        set_parsed(
+            &mut self.max_concurrent_catalogs,
+            "X5_MAX_CONCURRENT_CATALOGS",
+        );
+        set_parsed(&mut self.sleep_jitter_millis, "X5_SLEEP_JITTER_MILLIS");
+        set_parsed(
+            &mut self.min_store_revisit_secs,
+            "X5_MIN_STORE_REVISIT_SECS",
+        );
+        set_list(&mut self.catalogs, "X5_CATALOGS");
+        set_parsed(&mut self.dry_run, "X5_DRY_RUN");
+        set_parsed(
+            &mut self.cookie_refresh_threshold,
+            "X5_COOKIE_REFRESH_THRESHOLD",
+        );
+        set_string(&mut self.user_agent, "X5_USER_AGENT");
+        set_list(&mut self.user_agents, "X5_USER_AGENTS");
+        set_string(&mut self.store_source, "X5_STORE_SOURCE");
+        set_string(&mut self.catalog_filter, "X5_CATALOG_FILTER");
+        set_parsed(
+            &mut self.cookie_wait_timeout_secs,
+            "X5_COOKIE_WAIT_TIMEOUT_SECS",
+        );
+        set_list(&mut self.challenge_markers, "X5_CHALLENGE_MARKERS");
+        set_parsed(&mut self.run_once, "X5_RUN_ONCE");
+        set_parsed(&mut self.max_loops, "X5_MAX_LOOPS");
+        set_parsed(&mut self.max_runtime_secs, "X5_MAX_RUNTIME_SECS");
+        set_parsed(&mut self.download_images, "X5_DOWNLOAD_IMAGES");
+        set_string(&mut self.image_store_path, "X5_IMAGE_STORE_PATH");
+        set_parsed(&mut self.max_store_distance_km, "X5_MAX_STORE_DISTANCE_KM");
+        set_parsed(&mut self.status_port, "X5_STATUS_PORT");
+        set_parsed(&mut self.metrics_port, "X5_METRICS_PORT");
+        set_string(&mut self.json_wait_selector, "X5_JSON_WAIT_SELECTOR");
+        set_string(&mut self.fetch_mode, "X5_FETCH_MODE");
+        set_string(&mut self.retailer, "X5_RETAILER");
+        set_parsed(&mut self.vacuum_every_loops, "X5_VACUUM_EVERY_LOOPS");
+        set_parsed(
+            &mut self.history_retention_days,
+            "X5_HISTORY_RETENTION_DAYS",
+        );
+        set_parsed(&mut self.min_catalog_products, "X5_MIN_CATALOG_PRODUCTS");
+        set_parsed(
+            &mut self.max_empty_products_ratio,
+            "X5_MAX_EMPTY_PRODUCTS_RATIO",
+        );
+        set_parsed(&mut self.window_width, "X5_WINDOW_WIDTH");
+        set_parsed(&mut self.window_height, "X5_WINDOW_HEIGHT");
+        set_list(&mut self.extra_browser_args, "X5_EXTRA_BROWSER_ARGS");
+        set_parsed(&mut self.catalog_timeout_secs, "X5_CATALOG_TIMEOUT_SECS");
+        set_parsed(&mut self.shuffle_stores, "X5_SHUFFLE_STORES");
+        set_parsed(&mut self.resume, "X5_RESUME");
+        set_parsed(
+            &mut self.max_requests_per_minute,
+            "X5_MAX_REQUESTS_PER_MINUTE",
+        );
+        set_parsed(
+            &mut self.cookie_min_validity_secs,
+            "X5_COOKIE_MIN_VALIDITY_SECS",
+        );
+        set_string(&mut self.json_output_dir, "X5_JSON_OUTPUT_DIR");
+        set_parsed(&mut self.compress_output, "X5_COMPRESS_OUTPUT");
+        set_parsed(
+            &mut self.block_backoff_base_secs,
+            "X5_BLOCK_BACKOFF_BASE_SECS",
+        );
+        set_parsed(
+            &mut self.block_backoff_max_secs,
+            "X5_BLOCK_BACKOFF_MAX_SECS",
+        );
+        set_string(&mut self.cookie_wait_selector, "X5_COOKIE_WAIT_SELECTOR");
+        set_parsed(
+            &mut self.cookie_page_timeout_secs,
+            "X5_COOKIE_PAGE_TIMEOUT_SECS",
+        );
+        set_string(&mut self.geocode_mode, "X5_GEOCODE_MODE");
+        set_string(&mut self.price_policy, "X5_PRICE_POLICY");
+        set_parsed(&mut self.store_lookup_retries, "X5_STORE_LOOKUP_RETRIES");
+        set_string(&mut self.raw_response_dir, "X5_RAW_RESPONSE_DIR");
+        set_parsed(
+            &mut self.restart_browser_every_stores,
+            "X5_RESTART_BROWSER_EVERY_STORES",
+        );
+        set_string(&mut self.status_bind_address, "X5_STATUS_BIND_ADDRESS");
+        set_string(&mut self.metrics_bind_address, "X5_METRICS_BIND_ADDRESS");
+    }
+
+    /// Problems that would make a run fail or silently misbehave: missing files, a browser
+    /// that does not start and unknown option values.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let retailer = self
+            .retailer
+            .as_deref()
+            .map(str::trim)
+            .unwrap_or("pyaterochka");
+        check_value(
+            &mut problems,
+            "retailer",
+            Some(retailer),
+            &["pyaterochka", "perekrestok"],
+        );
+        check_value(
+            &mut problems,
+            "store_source",
+            self.store_source.as_deref(),
+            &["coords", "db"],
+        );
+        check_value(
+            &mut problems,
+            "fetch_mode",
+            self.fetch_mode.as_deref(),
+            &["browser", "http"],
+        );
+        check_value(
+            &mut problems,
+            "geocode_mode",
+            self.geocode_mode.as_deref(),
+            &["browser", "http"],
+        );
+        check_value(
+            &mut problems,
+            "catalog_filter",
+            self.catalog_filter.as_deref(),
+            &["default", "price_asc", "price_desc", "random"],
+        );
+        check_value(
+            &mut problems,
+            "price_policy",
+            self.price_policy.as_deref(),
+            &["both", "card_only", "regular_only"],
+        );
+        check_parent_dir(&mut problems, "db_path", self.db_path.as_deref());
+        check_parent_dir(
+            &mut problems,
+            "cookies_store_path",
+            self.cookies_store_path.as_deref(),
+        );
+        if self.store_source.as_deref() != Some("db") {
+            let default_path = format!("{retailer}_stores_coord.json");
+            let path = self
+                .pyaterochka_stores_coord_path
+                .as_deref()
+                .unwrap_or(&default_path);
+            if !Path::new(path).is_file() {
+                problems.push(format!("pyaterochka_stores_coord_path: {path} not found"));
+            }
+        }
+        if let Some(path) = self.browser_executable.as_deref() {
+            match std::process::Command::new(path).arg("--version").output() {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => problems.push(format!(
+                    "browser_executable: {path} --version exited with {}",
+                    output.status
+                )),
+                Err(e) => problems.push(format!("browser_executable: can't run {path}: {e}")),
+            }
+        }
+        let bind_addresses = [
+            ("status_bind_address", &self.status_bind_address),
+            ("metrics_bind_address", &self.metrics_bind_address),
+        ];
+        for (name, address) in bind_addresses {
+            if let Some(address) = address.as_deref()
+                && address.trim().parse::<std::net::IpAddr>().is_err()
+            {
+                problems.push(format!("{name}: {address:?} is not an IP address"));
+            }
+        }
+        if self.window_width.is_some() != self.window_height.is_some() {
+            problems.push("window_width and window_height must be set together".into());
+        }
+        for catalog in self.catalogs.iter().flatten() {
+            let known = match retailer {
+                "perekrestok" => perekrestok::Catalog::from_id_or_name(catalog.trim()).is_some(),
+                _ => pyaterochka::Catalog::from_id_or_name(catalog.trim()).is_some(),
+            };
+            if !known {
+                problems.push(format!("catalogs: unknown catalog {catalog:?}"));
+            }
+        }
+
+        problems
+    }
+}
+
+/// Top-level keys of the JSON config that are not `Config` fields (typos are otherwise
+/// silently ignored).
+pub fn unknown_fields(json: &str) -> serde_json::Result<Vec<String>> {
+    let known = serde_json::to_value(Config::default())?;
+    let value = serde_json::from_str::<serde_json::Value>(json)?;
+    let Some(fields) = value.as_object() else {
+        return Ok(Vec::new());
+    };
+
+    Ok(fields
+        .keys()
+        .filter(|k| known.get(k.as_str()).is_none())
+        .cloned()
+        .collect())
+}
+
+fn check_value(problems: &mut Vec<String>, name: &str, value: Option<&str>, allowed: &[&str]) {
+    if let Some(value) = value
+        && !allowed.contains(&value.trim())
+    {
+        problems.push(format!(
+            "{name}: unknown value {value:?}, expected one of {allowed:?}"
+        ));
+    }
+}
+
+fn check_parent_dir(problems: &mut Vec<String>, name: &str, path: Option<&str>) {
+    let Some(parent) = path.and_then(|v| Path::new(v).parent()) else {
+        return;
+    };
+    if !parent.as_os_str().is_empty() && !parent.is_dir() {
+        problems.push(format!(
+            "{name}: directory {} does not exist",
+            parent.display()
+        ));
+    }
+}
@@ -1,68 +1,381 @@
-use crate::{error::Result, parser::models::pyaterochka::{StoreInfo, CatalogInfoWithTime}};
-use rusqlite::Connection;
-use std::sync::{Arc, LazyLock, OnceLock, Mutex};
+use crate::{
+    error::Result,
+    parser::models::pyaterochka::{CatalogInfoWithTime, StoreInfo, match_brand},
+};
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params, types::ValueRef};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::Duration;
+use tracing::warn;
 
 static DB_PATH: OnceLock<String> = OnceLock::new();
 
 pub fn init(path: Option<&str>) -> &String {
-    DB_PATH.get_or_init(|| {
-        path.unwrap_or("database.sqlite").into()
+    DB_PATH.get_or_init(|| path.unwrap_or("database.sqlite").into())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DbOptions {
+    pub wal: bool,
+    pub busy_timeout_ms: u64,
+}
+
+static DB_OPTIONS: OnceLock<DbOptions> = OnceLock::new();
+
+pub fn init_options(wal: Option<bool>, busy_timeout_ms: Option<u64>) -> &'static DbOptions {
+    DB_OPTIONS.get_or_init(|| DbOptions {
+        wal: wal.unwrap_or(true),
+        busy_timeout_ms: busy_timeout_ms.unwrap_or(5000),
     })
 }
 
-static CONN: LazyLock<Arc<Mutex<Connection>>> = LazyLock::new(|| {
-    let conn = Connection::open(init(None)).unwrap();
-    conn.execute_batch(
-        r#"
-        BEGIN;
-        CREATE TABLE IF NOT EXISTS pyaterochka_stores (
-            id TEXT PRIMARY KEY,
-            address TEXT,
-            city TEXT,
-            inserted_at INTEGER
-        );
-        CREATE TABLE IF NOT EXISTS pyaterochka_products (
-            id TEXT PRIMARY KEY,
-            name TEXT,
-            category TEXT,
-            brand TEXT,
-            rating REAL,
-            rates_count INTEGER,
-            image TEXT,
-            property TEXT,
-            updated_at INTEGER,
-            inserted_at INTEGER
-        );
-        CREATE TABLE IF NOT EXISTS pyaterochka_product_price_history (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            store_id TEXT,
-            product_id TEXT,
-            price REAL,
-            card_price REAL,
-            inserted_at INTEGER
-        );
-        CREATE INDEX IF NOT EXISTS idx_pph_store_id ON pyaterochka_product_price_history(store_id);
-        CREATE INDEX IF NOT EXISTS idx_pph_product_id ON pyaterochka_product_price_history(product_id);
-        COMMIT;
-        "#,
-    )
-    .expect("Failed to execute batch");
-    Arc::new(Mutex::new(conn))
+pub const DEFAULT_TABLE_PREFIX: &str = "pyaterochka";
+
+/// Which prices are written into `{p}_product_price_history`, the other column is NULL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PricePolicy {
+    Both,
+    CardOnly,
+    RegularOnly,
+}
+
+impl PricePolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "both" => Some(Self::Both),
+            "card_only" => Some(Self::CardOnly),
+            "regular_only" => Some(Self::RegularOnly),
+            _ => None,
+        }
+    }
+
+    /// `(price, card_price)` to be stored
+    fn apply(self, price: f64, card_price: f64) -> (Option<f64>, Option<f64>) {
+        match self {
+            Self::Both => (Some(price), Some(card_price)),
+            Self::CardOnly => (None, Some(card_price)),
+            Self::RegularOnly => (Some(price), None),
+        }
+    }
+}
+
+static PRICE_POLICY: OnceLock<PricePolicy> = OnceLock::new();
+
+pub fn init_price_policy(policy: Option<&str>) -> PricePolicy {
+    *PRICE_POLICY.get_or_init(|| {
+        policy.map_or(PricePolicy::Both, |name| {
+            PricePolicy::from_name(name).unwrap_or_else(|| {
+                warn!("Unknown price_policy {name:?}, using both");
+                PricePolicy::Both
+            })
+        })
+    })
+}
+
+pub fn price_policy() -> PricePolicy {
+    init_price_policy(None)
+}
+
+static TABLE_PREFIX: OnceLock<String> = OnceLock::new();
+
+/// Prefix of the tables (`<prefix>_stores`, `<prefix>_products`, ...), so several
+/// retailers can share one database. Only ASCII letters, digits and `_` are kept.
+pub fn init_table_prefix(prefix: Option<&str>) -> &'static str {
+    TABLE_PREFIX.get_or_init(|| {
+        let prefix = prefix
+            .unwrap_or(DEFAULT_TABLE_PREFIX)
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect::<String>();
+        if prefix.is_empty() {
+            DEFAULT_TABLE_PREFIX.into()
+        } else {
+            prefix
+        }
+    })
+}
+
+pub fn table_prefix() -> &'static str {
+    init_table_prefix(None)
+}
+
+/// Substitutes `{p}` in a statement template with the table prefix.
+fn sql(template: &str, prefix: &str) -> String {
+    template.replace("{p}", prefix)
+}
+
+const MIGRATIONS: &[&str] = &[
+    r#"
+    CREATE TABLE IF NOT EXISTS {p}_stores (
+        id TEXT PRIMARY KEY,
+        address TEXT,
+        city TEXT,
+        inserted_at INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS {p}_products (
+        id TEXT PRIMARY KEY,
+        name TEXT,
+        category TEXT,
+        brand TEXT,
+        rating REAL,
+        rates_count INTEGER,
+        image TEXT,
+        property TEXT,
+        updated_at INTEGER,
+        inserted_at INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS {p}_product_price_history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        store_id TEXT,
+        product_id TEXT,
+        price REAL,
+        card_price REAL,
+        inserted_at INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS {p}_idx_pph_store_id ON {p}_product_price_history(store_id);
+    CREATE INDEX IF NOT EXISTS {p}_idx_pph_product_id ON {p}_product_price_history(product_id);
+    "#,
+    r#"
+    ALTER TABLE {p}_products ADD COLUMN uom TEXT;
+    ALTER TABLE {p}_products ADD COLUMN step TEXT;
+    ALTER TABLE {p}_products ADD COLUMN stock_limit TEXT;
+    ALTER TABLE {p}_products ADD COLUMN initial_weight_step TEXT;
+    ALTER TABLE {p}_products ADD COLUMN min_weight TEXT;
+    ALTER TABLE {p}_products ADD COLUMN is_available INTEGER;
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS {p}_product_labels (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        store_id TEXT,
+        product_id TEXT,
+        label_text TEXT,
+        inserted_at INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS {p}_idx_ppl_store_product ON {p}_product_labels(store_id, product_id);
+    "#,
+    r#"
+    ALTER TABLE {p}_stores ADD COLUMN parsed_at INTEGER;
+    "#,
+    r#"
+    ALTER TABLE {p}_product_price_history ADD COLUMN price_per_unit REAL;
+    "#,
+    r#"
+    CREATE INDEX IF NOT EXISTS {p}_idx_pph_product_store_time
+        ON {p}_product_price_history(product_id, store_id, inserted_at);
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS {p}_coord_stores (
+        lat_key INTEGER,
+        lon_key INTEGER,
+        store_id TEXT,
+        updated_at INTEGER,
+        PRIMARY KEY (lat_key, lon_key)
+    );
+    "#,
+    r#"
+    ALTER TABLE {p}_products ADD COLUMN image_path TEXT;
+    "#,
+    r#"
+    ALTER TABLE {p}_stores ADD COLUMN region TEXT;
+    "#,
+    r#"
+    ALTER TABLE {p}_product_price_history ADD COLUMN discount_percent REAL;
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS {p}_availability (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        store_id TEXT,
+        product_id TEXT,
+        is_available INTEGER,
+        inserted_at INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS {p}_idx_pa_store_product_time
+        ON {p}_availability(store_id, product_id, inserted_at);
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS {p}_runs (
+        run_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        started_at INTEGER,
+        finished_at INTEGER,
+        stores_parsed INTEGER DEFAULT 0,
+        products_inserted INTEGER DEFAULT 0,
+        errors INTEGER DEFAULT 0
+    );
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS {p}_state (
+        key TEXT PRIMARY KEY,
+        value TEXT,
+        updated_at INTEGER
+    );
+    "#,
+    r#"
+    ALTER TABLE {p}_stores ADD COLUMN lat REAL;
+    ALTER TABLE {p}_stores ADD COLUMN lon REAL;
+    "#,
+    r#"
+    ALTER TABLE {p}_runs ADD COLUMN price_rows_inserted INTEGER DEFAULT 0;
+    "#,
+    r#"
+    CREATE VIRTUAL TABLE IF NOT EXISTS {p}_products_fts
+        USING fts5(name, content='{p}_products', content_rowid='rowid');
+    CREATE TRIGGER IF NOT EXISTS {p}_products_fts_insert AFTER INSERT ON {p}_products BEGIN
+        INSERT INTO {p}_products_fts (rowid, name) VALUES (new.rowid, new.name);
+    END;
+    CREATE TRIGGER IF NOT EXISTS {p}_products_fts_delete AFTER DELETE ON {p}_products BEGIN
+        INSERT INTO {p}_products_fts ({p}_products_fts, rowid, name)
+            VALUES ('delete', old.rowid, old.name);
+    END;
+    CREATE TRIGGER IF NOT EXISTS {p}_products_fts_update AFTER UPDATE OF name ON {p}_products BEGIN
+        INSERT INTO {p}_products_fts ({p}_products_fts, rowid, name)
+            VALUES ('delete', old.rowid, old.name);
+        INSERT INTO {p}_products_fts (rowid, name) VALUES (new.rowid, new.name);
+    END;
+    INSERT INTO {p}_products_fts ({p}_products_fts) VALUES ('rebuild');
+    "#,
+];
+
+pub fn schema_version(conn: &Connection, prefix: &str) -> Result<usize> {
+    let version = conn
+        .query_row(
+            &sql(
+                "SELECT value FROM {p}_meta WHERE key = 'schema_version'",
+                prefix,
+            ),
+            [],
+            |r| r.get::<_, String>(0),
+        )
+        .optional()?;
+
+    Ok(version.and_then(|v| v.parse().ok()).unwrap_or(0))
+}
+
+pub fn run_migrations(conn: &mut Connection, prefix: &str) -> Result<()> {
+    conn.execute_batch(&sql(
+        "CREATE TABLE IF NOT EXISTS {p}_meta (key TEXT PRIMARY KEY, value TEXT)",
+        prefix,
+    ))?;
+    let version = schema_version(conn, prefix)?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(&sql(migration, prefix))?;
+        tx.execute(
+            &sql(
+                r#"INSERT INTO {p}_meta (key, value) VALUES ('schema_version', ?1)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value"#,
+                prefix,
+            ),
+            ((i + 1).to_string(),),
+        )?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+fn open_connection() -> Result<Connection> {
+    open_connection_at(init(None), init_options(None, None))
+}
+
+fn open_connection_at(path: &str, options: &DbOptions) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(Duration::from_millis(options.busy_timeout_ms))?;
+    if options.wal {
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    }
+
+    Ok(conn)
+}
+
+static MIGRATED_PREFIXES: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// Runs the migrations for `prefix` once per process.
+fn ensure_schema(conn: &mut Connection, prefix: &str) -> Result<()> {
+    if MIGRATED_PREFIXES.lock().unwrap().contains(prefix) {
+        return Ok(());
+    }
+    run_migrations(conn, prefix)?;
+    MIGRATED_PREFIXES.lock().unwrap().insert(prefix.to_string());
+
+    Ok(())
+}
+
+/// The process-wide connection. Stores are written one after another, so a single
+/// connection is enough; other processes on the same file wait within busy_timeout.
+static CONN: LazyLock<Mutex<Connection>> = LazyLock::new(|| {
+    let mut conn = open_connection().expect("Failed to open database");
+    ensure_schema(&mut conn, table_prefix()).expect("Failed to run migrations");
+    Mutex::new(conn)
 });
 
-pub fn pyaterochka_insert_data(store_info: &StoreInfo, catalogs: &[CatalogInfoWithTime]) -> Result<()> {
+/// Rows actually changed by `insert_data`.
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+pub struct InsertStats {
+    /// Inserted or updated products
+    pub products_upserted: usize,
+    /// New price history rows, unchanged prices are not inserted
+    pub price_rows_inserted: usize,
+}
+
+pub fn pyaterochka_insert_data(
+    store_info: &StoreInfo,
+    catalogs: &[CatalogInfoWithTime],
+) -> Result<InsertStats> {
+    insert_data(table_prefix(), store_info, catalogs)
+}
+
+/// Same as `pyaterochka_insert_data`, but on a caller provided connection.
+pub fn pyaterochka_insert_data_with_conn(
+    conn: &mut Connection,
+    store_info: &StoreInfo,
+    catalogs: &[CatalogInfoWithTime],
+) -> Result<InsertStats> {
+    insert_data_with_conn(conn, table_prefix(), store_info, catalogs)
+}
+
+/// Same as `pyaterochka_insert_data`, but into the tables of `prefix`.
+pub fn insert_data(
+    prefix: &str,
+    store_info: &StoreInfo,
+    catalogs: &[CatalogInfoWithTime],
+) -> Result<InsertStats> {
     let mut conn = CONN.lock().unwrap();
-    let tx = conn.transaction()?;
+    ensure_schema(&mut conn, prefix)?;
+    insert_data_with_conn(&mut conn, prefix, store_info, catalogs)
+}
+
+/// Does the work of `insert_data` on a caller provided connection (e.g. in-memory);
+/// the schema is expected to be migrated with `run_migrations`.
+pub fn insert_data_with_conn(
+    conn: &mut Connection,
+    prefix: &str,
+    store_info: &StoreInfo,
+    catalogs: &[CatalogInfoWithTime],
+) -> Result<InsertStats> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
     let now = chrono::Utc::now().timestamp();
+    let mut stats = InsertStats::default();
+    let price_policy = price_policy();
 
     tx.execute(
-        "INSERT OR IGNORE INTO pyaterochka_stores (id, address, city, inserted_at) VALUES (?1, ?2, ?3, ?4)",
-        (&store_info.id, &store_info.address, &store_info.city, &now),
+        &sql("INSERT OR IGNORE INTO {p}_stores (id, address, city, inserted_at, region) VALUES (?1, ?2, ?3, ?4, ?5)", prefix),
+        (&store_info.id, &store_info.address, &store_info.city, &now, &store_info.region),
+    )?;
+    tx.execute(
+        &sql(
+            "UPDATE {p}_stores SET parsed_at = ?2, region = COALESCE(?3, region) WHERE id = ?1",
+            prefix,
+        ),
+        (&store_info.id, &now, &store_info.region),
     )?;
 
     {
-        let mut stmt_insert_product = tx.prepare(
-            r#"INSERT INTO pyaterochka_products (
+        let mut stmt_insert_product = tx.prepare(&sql(
+            r#"INSERT INTO {p}_products (
                 id,
                 name,
                 category,
@@ -72,44 +385,95 @@ pub fn pyaterochka_insert_data(store_info: &StoreInfo, catalogs: &[CatalogInfoWi
                 image,
                 property,
                 updated_at,
-                inserted_at
+                inserted_at,
+                uom,
+                step,
+                stock_limit,
+                initial_weight_step,
+                min_weight,
+                is_available
             )
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)
             ON CONFLICT(id) DO UPDATE SET
-                name        = excluded.name,
-                category    = excluded.category,
-                brand       = excluded.brand,
-                rating      = excluded.rating,
-                rates_count = excluded.rates_count,
-                image       = excluded.image,
-                property    = excluded.property,
-                updated_at  = excluded.updated_at"#
-        )?;
+                name                = excluded.name,
+                category            = excluded.category,
+                brand               = excluded.brand,
+                rating              = excluded.rating,
+                rates_count         = excluded.rates_count,
+                image               = excluded.image,
+                property            = excluded.property,
+                updated_at          = excluded.updated_at,
+                uom                 = excluded.uom,
+                step                = excluded.step,
+                stock_limit         = excluded.stock_limit,
+                initial_weight_step = excluded.initial_weight_step,
+                min_weight          = excluded.min_weight,
+                is_available        = excluded.is_available"#,
+            prefix,
+        ))?;
 
         let mut stmt_insert_product_price_history = tx.prepare(
-            r#"INSERT INTO pyaterochka_product_price_history (store_id, product_id, price, card_price, inserted_at)
-            SELECT ?1, ?2, ?3, ?4, ?5
+            &sql(r#"INSERT INTO {p}_product_price_history (store_id, product_id, price, card_price, inserted_at, price_per_unit, discount_percent)
+            SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7
             WHERE NOT EXISTS (
                 SELECT 1
-                FROM pyaterochka_product_price_history p
+                FROM {p}_product_price_history p
                 WHERE p.store_id = ?1
                   AND p.product_id = ?2
                   AND p.inserted_at = (
                       SELECT inserted_at
-                      FROM pyaterochka_product_price_history
+                      FROM {p}_product_price_history
                       WHERE store_id = ?1 AND product_id = ?2
                       ORDER BY inserted_at DESC
                       LIMIT 1
                   )
-                  AND p.price = ?3
-                  AND p.card_price = ?4
-            )"#
+                  AND p.price IS ?3
+                  AND p.card_price IS ?4
+            )"#, prefix)
         )?;
 
+        let mut stmt_insert_product_label = tx.prepare(&sql(
+            r#"INSERT INTO {p}_product_labels (store_id, product_id, label_text, inserted_at)
+            SELECT ?1, ?2, ?3, ?4
+            WHERE NOT EXISTS (
+                SELECT 1
+                FROM {p}_product_labels
+                WHERE store_id = ?1 AND product_id = ?2 AND label_text = ?3 AND inserted_at = ?4
+            )"#,
+            prefix,
+        ))?;
+
+        let mut stmt_insert_availability = tx.prepare(&sql(
+            r#"INSERT INTO {p}_availability (store_id, product_id, is_available, inserted_at)
+            SELECT ?1, ?2, ?3, ?4
+            WHERE NOT EXISTS (
+                SELECT 1
+                FROM (
+                    SELECT is_available
+                    FROM {p}_availability
+                    WHERE store_id = ?1 AND product_id = ?2
+                    ORDER BY inserted_at DESC, id DESC
+                    LIMIT 1
+                ) latest
+                WHERE latest.is_available = ?3
+            )"#,
+            prefix,
+        ))?;
+
+        let mut inserted_labels = HashSet::new();
+        // The same product may be listed in several catalogs of the store
+        let mut inserted_products = HashSet::new();
+
         for c in catalogs.iter() {
             for p in c.info.products.iter() {
-                let brand = c.info.brand_list.iter().find(|v| p.name.contains(*v));
-                stmt_insert_product.execute((
+                if !inserted_products.insert((&p.id, p.price.to_bits(), p.card_price.to_bits())) {
+                    continue;
+                }
+                let brand = p
+                    .brand
+                    .as_ref()
+                    .or_else(|| match_brand(&p.name, &c.info.brand_list));
+                stats.products_upserted += stmt_insert_product.execute(params![
                     &p.id,
                     &p.name,
                     &c.info.name,
@@ -120,33 +484,747 @@ pub fn pyaterochka_insert_data(store_info: &StoreInfo, catalogs: &[CatalogInfoWi
                     &p.property,
                     &c.time,
                     &c.time,
-                ))?;
-                stmt_insert_product_price_history.execute((
+                    &p.uom,
+                    &p.step,
+                    &p.stock_limit,
+                    &p.initial_weight_step,
+                    &p.min_weight,
+                    &p.is_available,
+                ])?;
+                if p.price > 0. {
+                    let (price, card_price) = price_policy.apply(p.price, p.card_price);
+                    stats.price_rows_inserted += stmt_insert_product_price_history.execute((
+                        &store_info.id,
+                        &p.id,
+                        price,
+                        card_price,
+                        &c.time,
+                        &p.price_per_unit,
+                        &p.discount_percent,
+                    ))?;
+                }
+                stmt_insert_availability.execute((
                     &store_info.id,
                     &p.id,
-                    &p.price,
-                    &p.card_price,
+                    &p.is_available,
                     &c.time,
                 ))?;
+                for label in p.labels.iter() {
+                    if inserted_labels.insert((&p.id, label)) {
+                        stmt_insert_product_label.execute((
+                            &store_info.id,
+                            &p.id,
+                            label,
+                            &c.time,
+                        ))?;
+                    }
+                }
             }
         }
     }
 
     tx.commit()?;
 
+    Ok(stats)
+}
+
+/// Returns `(inserted_at, price, card_price)` rows ordered by time; a price is `None` if it
+/// was not recorded because of `PricePolicy`.
+pub fn get_price_history(
+    product_id: &str,
+    store_id: &str,
+) -> Result<Vec<(i64, Option<f64>, Option<f64>)>> {
+    let conn = CONN.lock().unwrap();
+    get_price_history_with_conn(&conn, table_prefix(), product_id, store_id)
+}
+
+fn get_price_history_with_conn(
+    conn: &Connection,
+    prefix: &str,
+    product_id: &str,
+    store_id: &str,
+) -> Result<Vec<(i64, Option<f64>, Option<f64>)>> {
+    let mut stmt = conn.prepare(&sql(
+        r#"SELECT inserted_at, price, card_price
+        FROM {p}_product_price_history
+        WHERE product_id = ?1 AND store_id = ?2
+        ORDER BY inserted_at, id"#,
+        prefix,
+    ))?;
+    let history = stmt
+        .query_map((product_id, store_id), |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(history)
+}
+
+/// Prices not recorded because of `PricePolicy` are 0.
+#[derive(Serialize, Debug, Clone)]
+pub struct PriceDrop {
+    pub store_id: String,
+    pub product_id: String,
+    pub old_price: f64,
+    pub new_price: f64,
+    pub old_card_price: f64,
+    pub new_card_price: f64,
+    /// Percentage decrease of `price` (negative if it went up)
+    pub price_drop_percent: f64,
+    /// Percentage decrease of `card_price` (negative if it went up)
+    pub card_price_drop_percent: f64,
+    pub inserted_at: i64,
+}
+
+fn drop_percent(old: f64, new: f64) -> f64 {
+    if old > 0. {
+        (old - new) / old * 100.
+    } else {
+        0.
+    }
+}
+
+/// Compares the two latest history rows of every product/store pair whose latest
+/// row was inserted at or after `since` and returns the ones that got cheaper.
+pub fn recent_price_drops(since: i64) -> Result<Vec<PriceDrop>> {
+    let conn = CONN.lock().unwrap();
+    recent_price_drops_with_conn(&conn, table_prefix(), since)
+}
+
+fn recent_price_drops_with_conn(
+    conn: &Connection,
+    prefix: &str,
+    since: i64,
+) -> Result<Vec<PriceDrop>> {
+    let mut stmt = conn.prepare(&sql(
+        r#"WITH ranked AS (
+            SELECT
+                store_id,
+                product_id,
+                price,
+                card_price,
+                inserted_at,
+                ROW_NUMBER() OVER (
+                    PARTITION BY store_id, product_id
+                    ORDER BY inserted_at DESC, id DESC
+                ) AS rn
+            FROM {p}_product_price_history
+        )
+        SELECT n.store_id, n.product_id, o.price, n.price, o.card_price, n.card_price, n.inserted_at
+        FROM ranked n
+        JOIN ranked o
+            ON o.store_id = n.store_id
+            AND o.product_id = n.product_id
+            AND o.rn = 2
+        WHERE n.rn = 1
+            AND n.inserted_at >= ?1
+            AND (n.price < o.price OR n.card_price < o.card_price)
+        ORDER BY n.inserted_at DESC, n.store_id, n.product_id"#,
+        prefix,
+    ))?;
+    let drops = stmt
+        .query_map((since,), |r| {
+            let price = |i: usize| r.get::<_, Option<f64>>(i).map(Option::unwrap_or_default);
+            let (old_price, new_price) = (price(2)?, price(3)?);
+            let (old_card_price, new_card_price) = (price(4)?, price(5)?);
+            Ok(PriceDrop {
+                store_id: r.get(0)?,
+                product_id: r.get(1)?,
+                old_price,
+                new_price,
+                old_card_price,
+                new_card_price,
+                price_drop_percent: drop_percent(old_price, new_price),
+                card_price_drop_percent: drop_percent(old_card_price, new_card_price),
+                inserted_at: r.get(6)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(drops)
+}
+
+pub fn set_product_image_paths(paths: &[(String, String)]) -> Result<()> {
+    let mut conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    {
+        let mut stmt = tx.prepare(&sql(
+            "UPDATE {p}_products SET image_path = ?2 WHERE id = ?1",
+            prefix,
+        ))?;
+        for (id, path) in paths {
+            stmt.execute((id, path))?;
+        }
+    }
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Ids of products whose latest availability record for the store is "unavailable".
+pub fn out_of_stock(store_id: &str) -> Result<Vec<String>> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    let mut stmt = conn.prepare(&sql(
+        r#"SELECT product_id
+        FROM (
+            SELECT
+                product_id,
+                is_available,
+                ROW_NUMBER() OVER (
+                    PARTITION BY product_id
+                    ORDER BY inserted_at DESC, id DESC
+                ) AS rn
+            FROM {p}_availability
+            WHERE store_id = ?1
+        )
+        WHERE rn = 1 AND is_available = 0
+        ORDER BY product_id"#,
+        prefix,
+    ))?;
+    let ids = stmt
+        .query_map((store_id,), |r| r.get(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(ids)
+}
+
+pub fn pyaterochka_stores() -> Result<Vec<StoreInfo>> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    let mut stmt = conn.prepare(&sql(
+        "SELECT id, address, city, region FROM {p}_stores ORDER BY id",
+        prefix,
+    ))?;
+    let stores = stmt
+        .query_map([], |r| {
+            Ok(StoreInfo {
+                id: r.get(0)?,
+                address: r.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                city: r.get(2)?,
+                region: r.get(3)?,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    Ok(stores)
+}
+
+/// Coordinates are rounded to 4 decimal places (~10 m) for the cache key.
+fn coord_key(v: f32) -> i64 {
+    (v as f64 * 10_000.).round() as i64
+}
+
+pub fn cache_coord_store(lat: f32, lon: f32, store_id: &str) -> Result<()> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    conn.execute(
+        &sql(
+            r#"INSERT INTO {p}_coord_stores (lat_key, lon_key, store_id, updated_at)
+        VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(lat_key, lon_key) DO UPDATE SET
+            store_id = excluded.store_id,
+            updated_at = excluded.updated_at"#,
+            prefix,
+        ),
+        (
+            coord_key(lat),
+            coord_key(lon),
+            store_id,
+            chrono::Utc::now().timestamp(),
+        ),
+    )?;
+
+    Ok(())
+}
+
+/// Location of the store as returned by the store lookup API.
+pub fn set_store_location(store_id: &str, lat: f64, lon: f64) -> Result<()> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    conn.execute(
+        &sql(
+            "UPDATE {p}_stores SET lat = ?2, lon = ?3 WHERE id = ?1",
+            prefix,
+        ),
+        (store_id, lat, lon),
+    )?;
+
+    Ok(())
+}
+
+/// Store previously resolved for these coordinates, if it is still in `{p}_stores`.
+pub fn cached_coord_store(lat: f32, lon: f32) -> Result<Option<StoreInfo>> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    let store = conn
+        .query_row(
+            &sql(
+                r#"SELECT s.id, s.address, s.city, s.region
+            FROM {p}_coord_stores c
+            JOIN {p}_stores s ON s.id = c.store_id
+            WHERE c.lat_key = ?1 AND c.lon_key = ?2"#,
+                prefix,
+            ),
+            (coord_key(lat), coord_key(lon)),
+            |r| {
+                Ok(StoreInfo {
+                    id: r.get(0)?,
+                    address: r.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                    city: r.get(2)?,
+                    region: r.get(3)?,
+                })
+            },
+        )
+        .optional()?;
+
+    Ok(store)
+}
+
+pub fn store_last_parsed(store_id: &str) -> Result<Option<i64>> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    let parsed_at = conn
+        .query_row(
+            &sql("SELECT parsed_at FROM {p}_stores WHERE id = ?1", prefix),
+            (store_id,),
+            |r| r.get::<_, Option<i64>>(0),
+        )
+        .optional()?;
+
+    Ok(parsed_at.flatten())
+}
+
+fn count_rows(template: &str) -> Result<u64> {
+    let conn = CONN.lock().unwrap();
+    let count = conn.query_row(&sql(template, table_prefix()), (), |r| r.get::<_, i64>(0))?;
+
+    Ok(count as u64)
+}
+
+pub fn count_products() -> Result<u64> {
+    count_rows("SELECT COUNT(*) FROM {p}_products")
+}
+
+pub fn count_stores() -> Result<u64> {
+    count_rows("SELECT COUNT(*) FROM {p}_stores")
+}
+
+pub fn count_price_history() -> Result<u64> {
+    count_rows("SELECT COUNT(*) FROM {p}_product_price_history")
+}
+
+/// Product found by `search_products` with its latest price row (any store).
+#[derive(Serialize, Debug, Clone)]
+pub struct ProductRow {
+    pub id: String,
+    pub name: String,
+    pub category: Option<String>,
+    pub brand: Option<String>,
+    pub store_id: Option<String>,
+    pub price: Option<f64>,
+    pub card_price: Option<f64>,
+    pub price_inserted_at: Option<i64>,
+}
+
+/// Every word of `query` is matched as a prefix, e.g. "молок прост" finds
+/// "Молоко Простоквашино".
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|v| format!("\"{}\"*", v.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Full-text search by product name, best matches first.
+pub fn search_products(query: &str) -> Result<Vec<ProductRow>> {
+    let fts_query = fts_query(query);
+    if fts_query.is_empty() {
+        return Ok(Vec::new());
+    }
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    let mut stmt = conn.prepare(
+        &sql(r#"SELECT p.id, p.name, p.category, p.brand, h.store_id, h.price, h.card_price, h.inserted_at
+        FROM {p}_products_fts f
+        JOIN {p}_products p ON p.rowid = f.rowid
+        LEFT JOIN {p}_product_price_history h ON h.id = (
+            SELECT id
+            FROM {p}_product_price_history
+            WHERE product_id = p.id
+            ORDER BY inserted_at DESC, id DESC
+            LIMIT 1
+        )
+        WHERE {p}_products_fts MATCH ?1
+        ORDER BY f.rank"#, prefix),
+    )?;
+    let products = stmt
+        .query_map((fts_query,), |r| {
+            Ok(ProductRow {
+                id: r.get(0)?,
+                name: r.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                category: r.get(2)?,
+                brand: r.get(3)?,
+                store_id: r.get(4)?,
+                price: r.get(5)?,
+                card_price: r.get(6)?,
+                price_inserted_at: r.get(7)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(products)
+}
+
+/// Small key-value state of the parser, e.g. the resume cursor.
+pub fn get_state(key: &str) -> Result<Option<String>> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    let value = conn
+        .query_row(
+            &sql("SELECT value FROM {p}_state WHERE key = ?1", prefix),
+            (key,),
+            |r| r.get::<_, Option<String>>(0),
+        )
+        .optional()?;
+
+    Ok(value.flatten())
+}
+
+pub fn set_state(key: &str, value: &str) -> Result<()> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    conn.execute(
+        &sql(
+            r#"INSERT INTO {p}_state (key, value, updated_at) VALUES (?1, ?2, ?3)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at"#,
+            prefix,
+        ),
+        (key, value, chrono::Utc::now().timestamp()),
+    )?;
+
     Ok(())
 }
 
+pub fn clear_state(key: &str) -> Result<()> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    conn.execute(&sql("DELETE FROM {p}_state WHERE key = ?1", prefix), (key,))?;
+
+    Ok(())
+}
+
+/// One pass over the stores, see `{p}_runs`.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct RunStats {
+    pub run_id: i64,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub stores_parsed: u64,
+    pub products_inserted: u64,
+    pub price_rows_inserted: u64,
+    pub errors: u64,
+}
+
+/// Opens a run row and returns its stats to be updated with `update_run`.
+pub fn start_run() -> Result<RunStats> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    let started_at = chrono::Utc::now().timestamp();
+    conn.execute(
+        &sql("INSERT INTO {p}_runs (started_at) VALUES (?1)", prefix),
+        (started_at,),
+    )?;
+
+    Ok(RunStats {
+        run_id: conn.last_insert_rowid(),
+        started_at,
+        ..Default::default()
+    })
+}
+
+pub fn update_run(stats: &RunStats) -> Result<()> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    conn.execute(
+        &sql(
+            r#"UPDATE {p}_runs
+        SET finished_at = ?2, stores_parsed = ?3, products_inserted = ?4, errors = ?5,
+            price_rows_inserted = ?6
+        WHERE run_id = ?1"#,
+            prefix,
+        ),
+        (
+            stats.run_id,
+            stats.finished_at,
+            stats.stores_parsed as i64,
+            stats.products_inserted as i64,
+            stats.errors as i64,
+            stats.price_rows_inserted as i64,
+        ),
+    )?;
+
+    Ok(())
+}
+
+/// The latest run, finished or in progress.
+pub fn current_run_stats() -> Result<Option<RunStats>> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    let stats = conn
+        .query_row(
+            &sql(
+                r#"SELECT run_id, started_at, finished_at, stores_parsed, products_inserted, errors,
+                price_rows_inserted
+            FROM {p}_runs
+            ORDER BY run_id DESC
+            LIMIT 1"#,
+                prefix,
+            ),
+            (),
+            |r| {
+                Ok(RunStats {
+                    run_id: r.get(0)?,
+                    started_at: r.get(1)?,
+                    finished_at: r.get(2)?,
+                    stores_parsed: r.get::<_, i64>(3)? as u64,
+                    products_inserted: r.get::<_, i64>(4)? as u64,
+                    errors: r.get::<_, i64>(5)? as u64,
+                    price_rows_inserted: r.get::<_, Option<i64>>(6)?.unwrap_or(0) as u64,
+                })
+            },
+        )
+        .optional()?;
+
+    Ok(stats)
+}
+
+/// Deletes price history rows older than `older_than_secs`, except the latest row of each
+/// (store_id, product_id), so the current price is kept. Returns the number of deleted rows.
+pub fn prune_price_history(older_than_secs: i64) -> Result<usize> {
+    let conn = CONN.lock().unwrap();
+    let cutoff = chrono::Utc::now().timestamp() - older_than_secs;
+    prune_price_history_with_conn(&conn, table_prefix(), cutoff)
+}
+
+fn prune_price_history_with_conn(conn: &Connection, prefix: &str, cutoff: i64) -> Result<usize> {
+    let deleted = conn.execute(
+        &sql(
+            r#"DELETE FROM {p}_product_price_history AS h
+        WHERE h.inserted_at < ?1
+          AND EXISTS (
+              SELECT 1
+              FROM {p}_product_price_history n
+              WHERE n.product_id = h.product_id
+                AND n.store_id = h.store_id
+                AND (n.inserted_at > h.inserted_at
+                     OR (n.inserted_at = h.inserted_at AND n.id > h.id))
+          )"#,
+            prefix,
+        ),
+        (cutoff,),
+    )?;
+
+    Ok(deleted)
+}
+
+/// Rebuilds the database file to return free pages. Needs exclusive access:
+/// concurrent writers wait (up to busy_timeout) until it finishes.
+pub fn vacuum() -> Result<()> {
+    let conn = CONN.lock().unwrap();
+    conn.execute_batch("VACUUM")?;
+
+    Ok(())
+}
+
+fn csv_field(v: &str) -> Cow<'_, str> {
+    if v.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", v.replace('"', "\"\"")).into()
+    } else {
+        v.into()
+    }
+}
+
+fn export_query_csv(query: &str, path: &str) -> Result<()> {
+    let conn = CONN.lock().unwrap();
+    let mut stmt = conn.prepare(query)?;
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+
+    let header = stmt
+        .column_names()
+        .into_iter()
+        .map(csv_field)
+        .collect::<Vec<_>>()
+        .join(",");
+    write!(out, "{header}\r\n")?;
+
+    let column_count = stmt.column_count();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut fields = Vec::with_capacity(column_count);
+        for i in 0..column_count {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => String::new(),
+                ValueRef::Integer(v) => v.to_string(),
+                ValueRef::Real(v) => v.to_string(),
+                ValueRef::Text(v) | ValueRef::Blob(v) => String::from_utf8_lossy(v).into_owned(),
+            };
+            fields.push(csv_field(&value).into_owned());
+        }
+        write!(out, "{}\r\n", fields.join(","))?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+pub fn export_products_csv(path: &str) -> Result<()> {
+    let prefix = table_prefix();
+    export_query_csv(&sql("SELECT * FROM {p}_products ORDER BY id", prefix), path)
+}
+
+pub fn export_price_history_csv(path: &str) -> Result<()> {
+    let prefix = table_prefix();
+    export_query_csv(
+        &sql(
+            "SELECT * FROM {p}_product_price_history ORDER BY id",
+            prefix,
+        ),
+        path,
+    )
+}
+
+fn export_query_jsonl(query: &str, path: &str) -> Result<()> {
+    let conn = CONN.lock().unwrap();
+    let mut stmt = conn.prepare(query)?;
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+
+    let column_names = stmt
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut object = serde_json::Map::with_capacity(column_names.len());
+        for (i, name) in column_names.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => serde_json::Value::Null,
+                ValueRef::Integer(v) => v.into(),
+                ValueRef::Real(v) => v.into(),
+                ValueRef::Text(v) | ValueRef::Blob(v) => String::from_utf8_lossy(v).into(),
+            };
+            object.insert(name.clone(), value);
+        }
+        serde_json::to_writer(&mut out, &object)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Writes stores as a GeoJSON FeatureCollection of points. Stores without a location from
+/// the API get the last requested point that resolved to them; stores with neither are
+/// skipped. Returns the number of exported stores.
+pub fn export_stores_geojson(path: &str) -> Result<usize> {
+    let conn = CONN.lock().unwrap();
+    let prefix = table_prefix();
+    let mut stmt = conn.prepare(&sql(
+        r#"SELECT
+            s.id,
+            s.address,
+            s.city,
+            s.region,
+            COALESCE(s.lat, (
+                SELECT c.lat_key / 10000.0 FROM {p}_coord_stores c
+                WHERE c.store_id = s.id ORDER BY c.updated_at DESC LIMIT 1
+            )),
+            COALESCE(s.lon, (
+                SELECT c.lon_key / 10000.0 FROM {p}_coord_stores c
+                WHERE c.store_id = s.id ORDER BY c.updated_at DESC LIMIT 1
+            ))
+        FROM {p}_stores s
+        ORDER BY s.id"#,
+        prefix,
+    ))?;
+    let mut features = Vec::new();
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let (Some(lat), Some(lon)) = (r.get::<_, Option<f64>>(4)?, r.get::<_, Option<f64>>(5)?)
+        else {
+            continue;
+        };
+        features.push(serde_json::json!({
+            "type": "Feature",
+            "geometry": { "type": "Point", "coordinates": [lon, lat] },
+            "properties": {
+                "id": r.get::<_, String>(0)?,
+                "address": r.get::<_, Option<String>>(1)?,
+                "city": r.get::<_, Option<String>>(2)?,
+                "region": r.get::<_, Option<String>>(3)?,
+            },
+        }));
+    }
+    let count = features.len();
+    let collection = serde_json::json!({ "type": "FeatureCollection", "features": features });
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer_pretty(&mut out, &collection)?;
+    out.flush()?;
+
+    Ok(count)
+}
+
+pub fn export_products_jsonl(path: &str) -> Result<()> {
+    let prefix = table_prefix();
+    export_query_jsonl(
+        &sql(
+            r#"SELECT
+            p.id,
+            p.name,
+            p.category,
+            p.brand,
+            p.rating,
+            p.rates_count,
+            p.image,
+            p.property,
+            p.updated_at,
+            h.store_id,
+            h.price,
+            h.card_price,
+            h.price_per_unit,
+            h.discount_percent,
+            h.inserted_at AS price_inserted_at
+        FROM {p}_products p
+        JOIN {p}_product_price_history h ON h.product_id = p.id
+        JOIN (
+            SELECT store_id, product_id, MAX(inserted_at) AS inserted_at
+            FROM {p}_product_price_history
+            GROUP BY store_id, product_id
+        ) latest
+            ON latest.store_id = h.store_id
+            AND latest.product_id = h.product_id
+            AND latest.inserted_at = h.inserted_at
+        ORDER BY p.id, h.store_id"#,
+            prefix,
+        ),
+        path,
+    )
+}
+
 // pub fn push_pyaterochka_products_batch(store_info: &StoreInfo, products: &[StdProduct]) -> Result<()> {
 //     let mut conn = CONN.lock().unwrap();
 //     let tx = conn.transaction()?;
 //     let now = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
-// 
+//
 //     tx.execute(
 //         "INSERT OR IGNORE INTO pyaterochka_stores (id, address, city, inserted_at) VALUES (?1, ?2, ?3, ?4)",
 //         (&store_info.id, &store_info.address, &store_info.city, &now),
 //     )?;
-// 
+//
 //     {
 //         let mut stmt_insert_product = tx.prepare(
 //             "INSERT OR IGNORE INTO pyaterochka_products (id, inserted_at) VALUES (?1, ?2)",
@@ -168,7 +1246,7 @@ pub fn pyaterochka_insert_data(store_info: &StoreInfo, catalogs: &[CatalogInfoWi
 //         let mut stmt_update = tx.prepare(
 //             "UPDATE pyaterochka_store_products SET data = ?1, updated_at = ?2 WHERE id = ?3",
 //         )?;
-// 
+//
 //         for product in products {
 //             if product.price == 0. || product.card_price == 0. {
 //                 continue;
@@ -176,14 +1254,14 @@ pub fn pyaterochka_insert_data(store_info: &StoreInfo, catalogs: &[CatalogInfoWi
 //             let product_id = product.id.as_str();
 //             let store_product_id = format!("{}_{product_id}", store_info.id);
 //             let product_data = serde_json::to_string(product)?;
-// 
+//
 //             stmt_insert_product.execute((product_id, &now))?;
-// 
+//
 //             let exists = stmt_exists
 //                 .query_row((&store_product_id,), |_| Ok(true))
 //                 .optional()?
 //                 .is_some();
-// 
+//
 //             if !exists {
 //                 stmt_insert_sp.execute((
 //                     &store_product_id,
@@ -201,7 +1279,7 @@ pub fn pyaterochka_insert_data(store_info: &StoreInfo, catalogs: &[CatalogInfoWi
 //             } else {
 //                 let old_data: String = stmt_old_data.query_row((&store_product_id,), |r| r.get(0))?;
 //                 let old_product: StdProduct = serde_json::from_str(&old_data)?;
-// 
+//
 //                 if old_product.price != product.price || old_product.card_price != product.card_price {
 //                     let price_info = serde_json::to_string(&serde_json::json!({
 //                         "price": product.price,
@@ -209,12 +1287,363 @@ pub fn pyaterochka_insert_data(store_info: &StoreInfo, catalogs: &[CatalogInfoWi
 //                     }))?;
 //                     stmt_history.execute((&store_product_id, &store_info.id, product_id, "price", &price_info, &now))?;
 //                 }
-// 
+//
 //                 stmt_update.execute((&product_data, &now, &store_product_id))?;
 //             }
 //         }
 //     }
-// 
+//
 //     tx.commit()?;
 //     Ok(())
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::models::pyaterochka::{CatalogInfo, ProductInfo, discount_percent};
+
+    const PREFIX: &str = "test";
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn
+            .prepare(&format!("PRAGMA table_info({PREFIX}_{table})"))
+            .unwrap();
+        stmt.query_map([], |r| r.get::<_, String>(1))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    fn migrated() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn, PREFIX).unwrap();
+        conn
+    }
+
+    #[test]
+    fn migrates_v1_database_to_latest() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // A database created before the migrations existed: v1 tables, no meta table
+        conn.execute_batch(&sql(MIGRATIONS[0], PREFIX)).unwrap();
+        conn.execute(
+            &sql(
+                "INSERT INTO {p}_products (id, name) VALUES ('1', ?1)",
+                PREFIX,
+            ),
+            ("Молоко Простоквашино",),
+        )
+        .unwrap();
+        assert!(columns(&conn, "meta").is_empty());
+
+        run_migrations(&mut conn, PREFIX).unwrap();
+        assert_eq!(schema_version(&conn, PREFIX).unwrap(), MIGRATIONS.len());
+
+        let products = columns(&conn, "products");
+        for column in ["uom", "step", "stock_limit", "is_available", "image_path"] {
+            assert!(products.contains(&column.to_string()), "products.{column}");
+        }
+        let history = columns(&conn, "product_price_history");
+        assert!(history.contains(&"price_per_unit".to_string()));
+        assert!(history.contains(&"discount_percent".to_string()));
+        let stores = columns(&conn, "stores");
+        for column in ["parsed_at", "region", "lat", "lon"] {
+            assert!(stores.contains(&column.to_string()), "stores.{column}");
+        }
+        assert!(columns(&conn, "runs").contains(&"price_rows_inserted".to_string()));
+        assert!(columns(&conn, "state").contains(&"value".to_string()));
+
+        // The FTS migration indexes the rows that existed before it
+        let found = conn
+            .query_row(
+                &sql(
+                    "SELECT rowid FROM {p}_products_fts WHERE {p}_products_fts MATCH ?1",
+                    PREFIX,
+                ),
+                (fts_query("простоквашино"),),
+                |r| r.get::<_, i64>(0),
+            )
+            .optional()
+            .unwrap();
+        assert!(found.is_some());
+
+        // Running again is a no-op
+        run_migrations(&mut conn, PREFIX).unwrap();
+        assert_eq!(schema_version(&conn, PREFIX).unwrap(), MIGRATIONS.len());
+    }
+
+    fn product(id: &str, price: f64, card_price: f64, labels: &[&str]) -> ProductInfo {
+        ProductInfo {
+            id: id.into(),
+            name: format!("Товар {id}"),
+            price,
+            card_price,
+            discount_percent: discount_percent(price, card_price),
+            price_per_unit: None,
+            rating: None,
+            rates_count: None,
+            image: None,
+            property: None,
+            uom: Some("шт".into()),
+            step: None,
+            stock_limit: None,
+            initial_weight_step: None,
+            min_weight: None,
+            is_available: true,
+            labels: labels.iter().map(|v| v.to_string()).collect(),
+            brand: None,
+        }
+    }
+
+    fn store() -> StoreInfo {
+        StoreInfo {
+            id: "35XY".into(),
+            address: "ул. Ленина, 1".into(),
+            ..Default::default()
+        }
+    }
+
+    fn catalog(time: i64, products: Vec<ProductInfo>) -> CatalogInfoWithTime {
+        CatalogInfoWithTime {
+            info: CatalogInfo {
+                id: "251C12887".into(),
+                name: "Молочная продукция и яйцо".into(),
+                brand_list: Vec::new(),
+                products,
+            },
+            time,
+        }
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {PREFIX}_{table}"), [], |r| {
+            r.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn insert_data_twice_does_not_duplicate_rows() {
+        let mut conn = migrated();
+        let catalogs = vec![catalog(
+            1_000,
+            vec![
+                product("1", 99.9, 89.9, &["-10%", "Хит"]),
+                product("2", 50., 50., &[]),
+                // The same product in another catalog of the store
+                product("1", 99.9, 89.9, &["-10%"]),
+            ],
+        )];
+
+        let stats = insert_data_with_conn(&mut conn, PREFIX, &store(), &catalogs).unwrap();
+        assert_eq!(stats.products_upserted, 2);
+        assert_eq!(stats.price_rows_inserted, 2);
+        let stats = insert_data_with_conn(&mut conn, PREFIX, &store(), &catalogs).unwrap();
+        assert_eq!(stats.products_upserted, 2);
+        assert_eq!(stats.price_rows_inserted, 0);
+
+        assert_eq!(count(&conn, "stores"), 1);
+        assert_eq!(count(&conn, "products"), 2);
+        assert_eq!(count(&conn, "product_price_history"), 2);
+        assert_eq!(count(&conn, "product_labels"), 2);
+        assert_eq!(count(&conn, "availability"), 2);
+    }
+
+    #[test]
+    fn insert_data_appends_price_history_only_on_change() {
+        let mut conn = migrated();
+        let insert = |conn: &mut Connection, time: i64, price: f64| {
+            let catalogs = vec![catalog(time, vec![product("1", price, price, &[])])];
+            insert_data_with_conn(conn, PREFIX, &store(), &catalogs)
+                .unwrap()
+                .price_rows_inserted
+        };
+
+        assert_eq!(insert(&mut conn, 1_000, 99.9), 1);
+        assert_eq!(insert(&mut conn, 2_000, 99.9), 0);
+        assert_eq!(insert(&mut conn, 3_000, 79.9), 1);
+        assert_eq!(insert(&mut conn, 4_000, 79.9), 0);
+        assert_eq!(insert(&mut conn, 5_000, 99.9), 1);
+        // Products without a price are stored, but not their price
+        assert_eq!(insert(&mut conn, 6_000, 0.), 0);
+
+        assert_eq!(count(&conn, "products"), 1);
+        assert_eq!(count(&conn, "product_price_history"), 3);
+    }
+
+    fn history_row(conn: &Connection, product_id: &str, price: f64, card_price: f64, time: i64) {
+        conn.execute(
+            &sql(
+                r#"INSERT INTO {p}_product_price_history
+                (store_id, product_id, price, card_price, inserted_at)
+                VALUES ('35XY', ?1, ?2, ?3, ?4)"#,
+                PREFIX,
+            ),
+            (product_id, price, card_price, time),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn price_history_is_read_back_in_order() {
+        let conn = migrated();
+        for (time, price, card_price) in [(2_000, 79.9, 69.9), (1_000, 99.9, 89.9)] {
+            history_row(&conn, "1", price, card_price, time);
+        }
+
+        // A row written with `PricePolicy::RegularOnly`
+        conn.execute(
+            &sql(
+                r#"INSERT INTO {p}_product_price_history
+                (store_id, product_id, price, card_price, inserted_at)
+                VALUES ('35XY', '1', 59.9, NULL, 3000)"#,
+                PREFIX,
+            ),
+            [],
+        )
+        .unwrap();
+
+        let history = get_price_history_with_conn(&conn, PREFIX, "1", "35XY").unwrap();
+        assert_eq!(
+            history,
+            vec![
+                (1_000, Some(99.9), Some(89.9)),
+                (2_000, Some(79.9), Some(69.9)),
+                (3_000, Some(59.9), None),
+            ]
+        );
+        assert!(
+            get_price_history_with_conn(&conn, PREFIX, "1", "other")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn recent_price_drops_returns_only_drops() {
+        let conn = migrated();
+        let rows = [
+            ("drop", 100., 90., 1_000),
+            ("rise", 100., 100., 1_000),
+            ("drop", 80., 72., 2_000),
+            ("rise", 120., 120., 2_000),
+            ("same", 50., 50., 2_000),
+            ("same", 50., 50., 3_000),
+        ];
+        for (product_id, price, card_price, time) in rows {
+            history_row(&conn, product_id, price, card_price, time);
+        }
+
+        let drops = recent_price_drops_with_conn(&conn, PREFIX, 1_500).unwrap();
+        assert_eq!(drops.len(), 1);
+        let drop = &drops[0];
+        assert_eq!(
+            (drop.store_id.as_str(), drop.product_id.as_str()),
+            ("35XY", "drop")
+        );
+        assert_eq!((drop.old_price, drop.new_price), (100., 80.));
+        assert_eq!((drop.old_card_price, drop.new_card_price), (90., 72.));
+        assert_eq!(drop.price_drop_percent, 20.);
+        assert_eq!(drop.card_price_drop_percent, 20.);
+        assert_eq!(drop.inserted_at, 2_000);
+
+        assert!(
+            recent_price_drops_with_conn(&conn, PREFIX, 2_500)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn concurrent_inserts_into_one_file() {
+        const STORES: usize = 8;
+        let path = std::env::temp_dir().join(format!("x5parser-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let remove_files = || {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{path}{suffix}"));
+            }
+        };
+        remove_files();
+        let options = DbOptions {
+            wal: true,
+            busy_timeout_ms: 5000,
+        };
+        run_migrations(&mut open_connection_at(&path, &options).unwrap(), PREFIX).unwrap();
+
+        let results = std::thread::scope(|s| {
+            let handles = (0..STORES)
+                .map(|i| {
+                    let path = &path;
+                    s.spawn(move || -> Result<()> {
+                        let mut conn = open_connection_at(path, &options)?;
+                        let tx = conn.transaction()?;
+                        for j in 0..200 {
+                            tx.execute(
+                                &sql(
+                                    r#"INSERT INTO {p}_product_price_history
+                                    (store_id, product_id, price, card_price, inserted_at)
+                                    VALUES (?1, ?2, ?3, 90, 1000)"#,
+                                    PREFIX,
+                                ),
+                                (format!("store{i}"), j.to_string(), 100. + i as f64),
+                            )?;
+                        }
+                        tx.commit()?;
+                        Ok(())
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let conn = open_connection_at(&path, &options).unwrap();
+        let rows: i64 = conn
+            .query_row(
+                &sql("SELECT COUNT(*) FROM {p}_product_price_history", PREFIX),
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        drop(conn);
+        remove_files();
+
+        // Separate connections, like processes sharing the file: no SQLITE_BUSY, the writers
+        // wait for each other within busy_timeout
+        for result in results {
+            result.map_err(|e| e.to_string()).unwrap();
+        }
+        assert_eq!(rows, STORES as i64 * 200);
+    }
+
+    #[test]
+    fn prune_price_history_keeps_latest_row() {
+        let mut conn = migrated();
+        for (time, price) in [(1_000, 100.), (2_000, 90.), (3_000, 80.)] {
+            let catalogs = vec![catalog(time, vec![product("1", price, price, &[])])];
+            insert_data_with_conn(&mut conn, PREFIX, &store(), &catalogs).unwrap();
+        }
+        for (time, price) in [(1_000, 10.), (5_000, 12.)] {
+            let catalogs = vec![catalog(time, vec![product("2", price, price, &[])])];
+            insert_data_with_conn(&mut conn, PREFIX, &store(), &catalogs).unwrap();
+        }
+
+        // Every row of product 1 is older than the cutoff, the latest one stays
+        assert_eq!(
+            prune_price_history_with_conn(&conn, PREFIX, 4_000).unwrap(),
+            3
+        );
+        let history = get_price_history_with_conn(&conn, PREFIX, "1", "35XY").unwrap();
+        assert_eq!(history, vec![(3_000, Some(80.), Some(80.))]);
+        let history = get_price_history_with_conn(&conn, PREFIX, "2", "35XY").unwrap();
+        assert_eq!(history, vec![(5_000, Some(12.), Some(12.))]);
+
+        assert_eq!(
+            prune_price_history_with_conn(&conn, PREFIX, 10_000).unwrap(),
+            0
+        );
+        assert_eq!(count(&conn, "product_price_history"), 2);
+    }
+}
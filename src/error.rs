@@ -1,9 +1,10 @@
-use thiserror::Error as ThisError; 
-use std::io::Error as StdIoError;
-use serde_json::Error as SerdeJsonError;
-use tokio::time::error::Elapsed as TokioTimeoutError;
 use chromiumoxide::error::CdpError as ChromeDevToolsProtocolError;
+use reqwest::Error as HttpError;
 use rusqlite::Error as DBError;
+use serde_json::Error as SerdeJsonError;
+use std::io::Error as StdIoError;
+use thiserror::Error as ThisError;
+use tokio::time::error::Elapsed as TokioTimeoutError;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -15,12 +16,178 @@ pub enum Error {
     #[error(transparent)]
     SerdeJson(#[from] SerdeJsonError),
 
+    #[error("failed to parse {context}: {source}")]
+    Parse {
+        context: String,
+        source: SerdeJsonError,
+    },
+
+    #[error("blocked or empty response from {url}")]
+    BlockedOrEmptyResponse { url: String },
+
+    #[error("HTTP {status} from {url}")]
+    HttpStatus { url: String, status: u16 },
+
+    #[error("challenge page ({marker}) at {url}")]
+    Challenge { url: String, marker: String },
+
+    #[error("suspicious response for {context}: {reason}")]
+    SuspiciousResponse { context: String, reason: String },
+
+    #[error("network unreachable while opening {url}: {reason}")]
+    NetworkUnreachable { url: String, reason: String },
+
+    #[error("connection reset while opening {url}: {reason}")]
+    ConnectionReset { url: String, reason: String },
+
+    #[error("navigation to {url} timed out")]
+    NavigationTimeout { url: String },
+
+    #[error("no store found near the requested point")]
+    StoreNotFound,
+
+    #[error("no valid coordinates in {path}")]
+    NoValidCoordinates { path: String },
+
     #[error(transparent)]
     DB(#[from] DBError),
 
     #[error(transparent)]
     Elapsed(#[from] TokioTimeoutError),
 
+    #[error("timed out: {context}")]
+    Timeout {
+        context: String,
+        source: TokioTimeoutError,
+    },
+
     #[error(transparent)]
     Io(#[from] StdIoError),
+
+    #[error(transparent)]
+    Http(#[from] HttpError),
+}
+
+/// Chrome net error codes (`net::ERR_*`) of failures before any response
+const NETWORK_UNREACHABLE_ERRORS: [&str; 8] = [
+    "ERR_NAME_NOT_RESOLVED",
+    "ERR_INTERNET_DISCONNECTED",
+    "ERR_ADDRESS_UNREACHABLE",
+    "ERR_NETWORK_CHANGED",
+    "ERR_CONNECTION_REFUSED",
+    "ERR_PROXY_CONNECTION_FAILED",
+    "ERR_TUNNEL_CONNECTION_FAILED",
+    "ERR_NETWORK_ACCESS_DENIED",
+];
+
+const CONNECTION_RESET_ERRORS: [&str; 4] = [
+    "ERR_CONNECTION_RESET",
+    "ERR_CONNECTION_CLOSED",
+    "ERR_CONNECTION_ABORTED",
+    "ERR_EMPTY_RESPONSE",
+];
+
+const NAVIGATION_TIMEOUT_ERRORS: [&str; 2] = ["ERR_TIMED_OUT", "ERR_CONNECTION_TIMED_OUT"];
+
+impl Error {
+    /// Maps a `page.goto` failure to a specific variant by the Chrome net error in it.
+    pub fn from_navigation(e: ChromeDevToolsProtocolError, url: &str) -> Self {
+        let reason = e.to_string();
+        let url = url.to_string();
+        if matches!(e, ChromeDevToolsProtocolError::Timeout)
+            || NAVIGATION_TIMEOUT_ERRORS.iter().any(|v| reason.contains(v))
+        {
+            Error::NavigationTimeout { url }
+        } else if CONNECTION_RESET_ERRORS.iter().any(|v| reason.contains(v)) {
+            Error::ConnectionReset { url, reason }
+        } else if NETWORK_UNREACHABLE_ERRORS
+            .iter()
+            .any(|v| reason.contains(v))
+        {
+            Error::NetworkUnreachable { url, reason }
+        } else {
+            Error::ChromeDevToolsProtocol(e)
+        }
+    }
+
+    /// The site refused to serve data (anti-bot page or empty response).
+    pub fn is_blocked(&self) -> bool {
+        matches!(
+            self,
+            Error::BlockedOrEmptyResponse { .. } | Error::Challenge { .. }
+        )
+    }
+
+    pub fn with_context(self, context: impl Into<String>) -> Self {
+        match self {
+            Error::SerdeJson(source) => Error::Parse {
+                context: context.into(),
+                source,
+            },
+            Error::Elapsed(source) => Error::Timeout {
+                context: context.into(),
+                source,
+            },
+            e => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://5ka.ru/api/catalog";
+
+    fn navigation(text: &str) -> Error {
+        Error::from_navigation(ChromeDevToolsProtocolError::ChromeMessage(text.into()), URL)
+    }
+
+    #[test]
+    fn navigation_timeouts() {
+        assert!(matches!(
+            Error::from_navigation(ChromeDevToolsProtocolError::Timeout, URL),
+            Error::NavigationTimeout { url } if url == URL
+        ));
+        assert!(matches!(
+            navigation("net::ERR_TIMED_OUT"),
+            Error::NavigationTimeout { .. }
+        ));
+        // Starts like a connection error, but is a timeout
+        assert!(matches!(
+            navigation("net::ERR_CONNECTION_TIMED_OUT"),
+            Error::NavigationTimeout { .. }
+        ));
+    }
+
+    #[test]
+    fn navigation_connection_resets() {
+        for text in ["net::ERR_CONNECTION_RESET", "net::ERR_EMPTY_RESPONSE"] {
+            assert!(matches!(
+                navigation(text),
+                Error::ConnectionReset { url, reason } if url == URL && reason.contains(&text[5..])
+            ));
+        }
+    }
+
+    #[test]
+    fn navigation_network_unreachable() {
+        for text in [
+            "net::ERR_NAME_NOT_RESOLVED",
+            "net::ERR_PROXY_CONNECTION_FAILED",
+        ] {
+            assert!(
+                matches!(navigation(text), Error::NetworkUnreachable { .. }),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn other_navigation_errors_are_kept() {
+        assert!(matches!(
+            navigation("net::ERR_ABORTED"),
+            Error::ChromeDevToolsProtocol(ChromeDevToolsProtocolError::ChromeMessage(_))
+        ));
+    }
 }
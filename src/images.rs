@@ -0,0 +1,102 @@
+use crate::error::Result;
+use crate::parser::models::pyaterochka::CatalogInfoWithTime;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+const DEFAULT_IMAGE_STORE_PATH: &str = "images";
+const DOWNLOAD_INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct ImageDownloader {
+    client: reqwest::Client,
+    dir: PathBuf,
+}
+
+fn image_extension(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    path.rsplit('/')
+        .next()
+        .and_then(|v| v.rsplit_once('.'))
+        .map(|(_, ext)| ext)
+        .filter(|ext| {
+            !ext.is_empty() && ext.len() <= 5 && ext.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+        .unwrap_or("jpg")
+}
+
+impl ImageDownloader {
+    pub fn new(dir: Option<&str>, user_agent: Option<&str>) -> Result<Self> {
+        let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(30));
+        if let Some(user_agent) = user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            dir: PathBuf::from(dir.unwrap_or(DEFAULT_IMAGE_STORE_PATH)),
+        })
+    }
+
+    pub fn image_path(&self, product_id: &str, url: &str) -> PathBuf {
+        let file_name = product_id.replace(|c: char| !c.is_alphanumeric() && c != '-', "_");
+        self.dir
+            .join(format!("{file_name}.{}", image_extension(url)))
+    }
+
+    /// Downloads the image unless it is already on disk. Returns the local path and
+    /// whether a request was made.
+    pub async fn download(&self, product_id: &str, url: &str) -> Result<(PathBuf, bool)> {
+        let path = self.image_path(product_id, url);
+        if tokio::fs::try_exists(&path).await? {
+            return Ok((path, false));
+        }
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let bytes = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let tmp_path = path.with_extension("part");
+        tokio::fs::write(&tmp_path, &bytes).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+
+        Ok((path, true))
+    }
+
+    /// Downloads images of all products, one request at a time with a pause between
+    /// requests. Returns `(product_id, local_path)` pairs of the images on disk.
+    pub async fn download_catalogs(
+        &self,
+        catalogs: &[CatalogInfoWithTime],
+    ) -> Vec<(String, String)> {
+        let mut seen = HashSet::new();
+        let mut paths = Vec::new();
+        for p in catalogs.iter().flat_map(|c| c.info.products.iter()) {
+            let Some(url) = p.image.as_deref().filter(|v| !v.is_empty()) else {
+                continue;
+            };
+            if !seen.insert(&p.id) {
+                continue;
+            }
+            match self.download(&p.id, url).await {
+                Ok((path, downloaded)) => {
+                    paths.push((p.id.clone(), path_string(&path)));
+                    if downloaded {
+                        tokio::time::sleep(DOWNLOAD_INTERVAL).await;
+                    }
+                }
+                Err(e) => warn!(product_id = %p.id, "Image download failed ({url}): {e}"),
+            }
+        }
+
+        paths
+    }
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
@@ -0,0 +1,57 @@
+use crate::error::Result;
+use crate::parser::models::pyaterochka::{CatalogInfoWithTime, StoreInfo};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Writes the result of each store into `{store_id}_{timestamp}.json`, an archive independent
+/// of the DB (`.json.gz` with `compress`).
+pub struct JsonOutput {
+    dir: PathBuf,
+    compress: bool,
+}
+
+#[derive(Serialize)]
+struct StoreDump<'a> {
+    store: &'a StoreInfo,
+    catalogs: &'a [CatalogInfoWithTime],
+}
+
+impl JsonOutput {
+    pub fn new(dir: &str, compress: bool) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            compress,
+        }
+    }
+
+    pub fn store_path(&self, store_id: &str, time: i64) -> PathBuf {
+        let file_name = store_id.replace(|c: char| !c.is_alphanumeric() && c != '-', "_");
+        let ext = if self.compress { "json.gz" } else { "json" };
+        self.dir.join(format!("{file_name}_{time}.{ext}"))
+    }
+
+    /// Written into a temporary file and renamed, so a crash never leaves a partial file.
+    pub async fn write_store(
+        &self,
+        store: &StoreInfo,
+        catalogs: &[CatalogInfoWithTime],
+    ) -> Result<PathBuf> {
+        let path = self.store_path(&store.id, chrono::Utc::now().timestamp());
+        let mut json = serde_json::to_vec(&StoreDump { store, catalogs })?;
+        if self.compress {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&json)?;
+            json = encoder.finish()?;
+        }
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".part");
+        tokio::fs::write(&tmp_path, &json).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+
+        Ok(path)
+    }
+}
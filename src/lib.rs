@@ -0,0 +1,18 @@
+pub mod browser_utils;
+mod build_info;
+mod config;
+pub mod db;
+pub mod error;
+pub mod images;
+pub mod json_output;
+pub mod metrics;
+pub mod parser;
+pub mod status;
+
+pub use build_info::build_info;
+pub use config::{Config, unknown_fields};
+pub use parser::models::pyaterochka::{CatalogInfo, CatalogInfoWithTime, ProductInfo, StoreInfo};
+pub use parser::perekrestok::Perekrestok;
+pub use parser::pipeline::{ParseConfig, ParseConfigBuilder, start_parsing, start_parsing_with};
+pub use parser::pyaterochka::{Catalog, Pyaterochka};
+pub use parser::retailer::Retailer;
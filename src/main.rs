@@ -1,37 +1,230 @@
-use serde::{Deserialize, Serialize};
-mod browser_utils;
-mod db;
-mod error;
-mod parser;
+use std::path::Path;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+use x5parser::parser::perekrestok;
+use x5parser::parser::pipeline::{DEFAULT_GRID_STEP, generate_coord_grid};
+use x5parser::parser::raw_responses::replay;
+use x5parser::{
+    Catalog, Config, ParseConfig, Perekrestok, Pyaterochka, build_info, db, start_parsing,
+    unknown_fields,
+};
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct Config<'a> {
-    pub db_path: Option<&'a str>,
-    pub browser_executable: Option<&'a str>,
-    pub cookies_store_path: Option<&'a str>,
-    pub pyaterochka_stores_coord_path: Option<&'a str>,
-    pub sleep_millis_for_each_catalog: Option<u64>,
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a String> {
+    args.iter()
+        .position(|v| v == flag)
+        .and_then(|v| args.get(v + 1))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
     let args = std::env::args().collect::<Vec<_>>();
-    let config_flag_pos = args.iter().position(|v| v == "-c");
-    let config_path = config_flag_pos.and_then(|v| args.get(v+1));
-    let config_json = config_path.and_then(|v| std::fs::read_to_string(v).ok()); 
-    let config = config_json.as_ref()
-        .and_then(|v| serde_json::from_str::<Config>(v).ok())
-        .unwrap_or_default();
-    let _ = db::init(config.db_path);
-    println!("{:#?}", config);
-    let parse_config = parser::pyaterochka::ParseConfig{ 
-        browser_executable: config.browser_executable, 
-        cookies_store_path: config.cookies_store_path, 
+    if args.iter().any(|v| v == "--info") {
+        print!("{}", build_info());
+        return Ok(());
+    }
+    let config_path = arg_value(&args, "-c");
+    // Without -c the defaults are used, but a given config file must be readable and valid
+    let config_json = match config_path {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read config {path}: {e}"))?,
+        ),
+        None => None,
+    };
+    let mut config = match (config_path, config_json.as_deref()) {
+        (Some(path), Some(json)) => serde_json::from_str::<Config>(json)
+            .map_err(|e| format!("Invalid config {path}: {e}"))?,
+        _ => Config::default(),
+    };
+    config.apply_env();
+    if args.iter().any(|v| v == "--once") {
+        config.run_once = Some(true);
+    }
+    if args.iter().any(|v| v == "--validate-config") {
+        let mut problems = Vec::new();
+        if let Some(json) = config_json.as_deref() {
+            let fields = unknown_fields(json)?;
+            problems.extend(fields.into_iter().map(|v| format!("unknown field {v:?}")));
+        }
+        problems.extend(config.validate());
+        if problems.is_empty() {
+            println!("Config OK");
+            return Ok(());
+        }
+        for problem in &problems {
+            println!("- {problem}");
+        }
+        return Err(format!("{} config problems", problems.len()).into());
+    }
+    let _ = db::init(config.db_path.as_deref());
+    let _ = db::init_options(config.db_wal, config.db_busy_timeout_ms);
+    let _ = db::init_price_policy(config.price_policy.as_deref());
+    let retailer = match config.retailer.as_deref().map(str::trim) {
+        None | Some("pyaterochka") => "pyaterochka",
+        Some("perekrestok") => "perekrestok",
+        Some(v) => {
+            warn!("Unknown retailer {v:?}, using pyaterochka");
+            "pyaterochka"
+        }
+    };
+    if args.iter().any(|v| v == "--list-catalogs") {
+        let catalogs = match retailer {
+            "perekrestok" => perekrestok::Catalog::all()
+                .map(|c| (c.as_catalog_id(), format!("{c:?}"), c.display_name()))
+                .collect::<Vec<_>>(),
+            _ => Catalog::all()
+                .map(|c| (c.as_catalog_id(), format!("{c:?}"), c.display_name()))
+                .collect::<Vec<_>>(),
+        };
+        for (id, name, display_name) in catalogs {
+            println!("{id:<9}  {name:<28}  {display_name}");
+        }
+        return Ok(());
+    }
+    let _ = db::init_table_prefix(config.table_prefix.as_deref().or(Some(retailer)));
+    if let Some(dir) = arg_value(&args, "--export-csv") {
+        std::fs::create_dir_all(dir)?;
+        let dir = Path::new(dir);
+        db::export_products_csv(&dir.join("products.csv").to_string_lossy())?;
+        db::export_price_history_csv(&dir.join("price_history.csv").to_string_lossy())?;
+        return Ok(());
+    }
+    if let Some(path) = arg_value(&args, "--export-jsonl") {
+        db::export_products_jsonl(path)?;
+        return Ok(());
+    }
+    if let Some(path) = arg_value(&args, "--export-geojson") {
+        let count = db::export_stores_geojson(path)?;
+        info!("Exported {count} stores into {path}");
+        return Ok(());
+    }
+    if args.iter().any(|v| v == "--stats") {
+        println!("stores: {}", db::count_stores()?);
+        println!("products: {}", db::count_products()?);
+        println!("price history rows: {}", db::count_price_history()?);
+        return Ok(());
+    }
+    if let Some(dir) = arg_value(&args, "--replay") {
+        let insert = !config.dry_run.unwrap_or(false);
+        let stats = match retailer {
+            "perekrestok" => replay(&Perekrestok, dir, insert, &config).await?,
+            _ => replay(&Pyaterochka, dir, insert, &config).await?,
+        };
+        info!(
+            "Replayed {} stores, {} catalog pages, {} failed files, {} rejected catalogs",
+            stats.stores, stats.catalog_pages, stats.failed_files, stats.rejected_catalogs
+        );
+        return Ok(());
+    }
+    if let Some(query) = arg_value(&args, "--search") {
+        let format_price = |v: Option<f64>| v.map_or("-".into(), |v| format!("{v:.2}"));
+        for p in db::search_products(query)? {
+            println!(
+                "{}  {}  {} / {}  {}",
+                p.id,
+                p.name,
+                format_price(p.price),
+                format_price(p.card_price),
+                p.store_id.as_deref().unwrap_or("-")
+            );
+        }
+        return Ok(());
+    }
+    if args.iter().any(|v| v == "--vacuum") {
+        db::vacuum()?;
+        info!("VACUUM done");
+        return Ok(());
+    }
+    if let Some(bbox) = arg_value(&args, "--gen-grid") {
+        let bbox = bbox
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let [min_lat, min_lon, max_lat, max_lon] = bbox[..] else {
+            return Err("--gen-grid expects min_lat,min_lon,max_lat,max_lon".into());
+        };
+        let step = arg_value(&args, "--grid-step")
+            .map(|v| v.parse::<f32>())
+            .transpose()?
+            .unwrap_or(DEFAULT_GRID_STEP);
+        let out = arg_value(&args, "--out")
+            .map(String::as_str)
+            .unwrap_or("pyaterochka_stores_coord_grid.json");
+        let grid = generate_coord_grid(min_lat, min_lon, max_lat, max_lon, step);
+        std::fs::write(out, serde_json::to_string_pretty(&grid)?)?;
+        info!("Generated {} coordinates into {out}", grid.len());
+        return Ok(());
+    }
+    info!("{:#?}", config);
+    let parse_config = ParseConfig {
+        browser_executable: config.browser_executable,
+        cookies_store_path: config.cookies_store_path,
         pyaterochka_stores_coord_path: config.pyaterochka_stores_coord_path,
         sleep_millis_for_each_catalog: config.sleep_millis_for_each_catalog,
+        cookie_refresh_headless: config.cookie_refresh_headless,
+        catalog_limit: config.catalog_limit,
+        proxy: config.proxy,
+        proxies: config.proxies,
+        max_concurrent_catalogs: config.max_concurrent_catalogs,
+        insert_into_db: None,
+        sleep_jitter_millis: config.sleep_jitter_millis,
+        min_store_revisit_secs: config.min_store_revisit_secs,
+        catalogs: config.catalogs,
+        dry_run: config.dry_run,
+        cookie_refresh_threshold: config.cookie_refresh_threshold,
+        user_agent: config.user_agent,
+        user_agents: config.user_agents,
+        store_source: config.store_source,
+        catalog_filter: config.catalog_filter,
+        cookie_wait_timeout_secs: config.cookie_wait_timeout_secs,
+        challenge_markers: config.challenge_markers,
+        run_once: config.run_once,
+        max_loops: config.max_loops,
+        max_runtime_secs: config.max_runtime_secs,
+        download_images: config.download_images,
+        image_store_path: config.image_store_path,
+        max_store_distance_km: config.max_store_distance_km,
+        status_port: config.status_port,
+        metrics_port: config.metrics_port,
+        json_wait_selector: config.json_wait_selector,
+        fetch_mode: config.fetch_mode,
+        vacuum_every_loops: config.vacuum_every_loops,
+        history_retention_days: config.history_retention_days,
+        min_catalog_products: config.min_catalog_products,
+        max_empty_products_ratio: config.max_empty_products_ratio,
+        window_width: config.window_width,
+        window_height: config.window_height,
+        extra_browser_args: config.extra_browser_args,
+        catalog_timeout_secs: config.catalog_timeout_secs,
+        shuffle_stores: config.shuffle_stores,
+        resume: config.resume,
+        max_requests_per_minute: config.max_requests_per_minute,
+        cookie_min_validity_secs: config.cookie_min_validity_secs,
+        config_path: config_path.cloned(),
+        json_output_dir: config.json_output_dir,
+        compress_output: config.compress_output,
+        block_backoff_base_secs: config.block_backoff_base_secs,
+        block_backoff_max_secs: config.block_backoff_max_secs,
+        cookie_wait_selector: config.cookie_wait_selector,
+        cookie_page_timeout_secs: config.cookie_page_timeout_secs,
+        geocode_mode: config.geocode_mode,
+        store_lookup_retries: config.store_lookup_retries,
+        raw_response_dir: config.raw_response_dir,
+        restart_browser_every_stores: config.restart_browser_every_stores,
+        status_bind_address: config.status_bind_address,
+        metrics_bind_address: config.metrics_bind_address,
+    };
+    let result = match retailer {
+        "perekrestok" => start_parsing(Perekrestok, &parse_config).await,
+        _ => start_parsing(Pyaterochka, &parse_config).await,
     };
-    if let Err(e) = parser::pyaterochka::start_parsing(&parse_config).await {
-        eprintln!("Error: {e}");
+    if let Err(e) = result {
+        error!("Error: {e}");
     }
     tokio::time::sleep(std::time::Duration::from_millis(500)).await;
     Ok(())
@@ -0,0 +1,65 @@
+//! Prometheus metrics, compiled to no-ops without the `metrics` feature.
+
+use std::time::Duration;
+
+/// Only local clients can reach the exporter unless `metrics_bind_address` is set.
+pub const DEFAULT_METRICS_BIND_ADDRESS: &str = "127.0.0.1";
+
+#[cfg(feature = "metrics")]
+pub fn install_exporter(port: Option<u16>, bind_address: Option<&str>) {
+    let Some(port) = port else {
+        return;
+    };
+    let bind_address = bind_address.unwrap_or(DEFAULT_METRICS_BIND_ADDRESS);
+    let address = match bind_address.trim().parse::<std::net::IpAddr>() {
+        Ok(ip) => std::net::SocketAddr::new(ip, port),
+        Err(e) => {
+            tracing::error!("Invalid metrics_bind_address {bind_address:?}: {e}");
+            return;
+        }
+    };
+    let result = metrics_exporter_prometheus::PrometheusBuilder::new()
+        .with_http_listener(address)
+        .install();
+    match result {
+        Ok(()) => tracing::info!("Metrics exporter listening on {address}"),
+        Err(e) => tracing::error!("Failed to install metrics exporter: {e}"),
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+pub fn install_exporter(port: Option<u16>, _bind_address: Option<&str>) {
+    if port.is_some() {
+        tracing::warn!("metrics_port is set, but x5parser was built without the metrics feature");
+    }
+}
+
+pub fn store_parsed() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("x5parser_stores_parsed_total").increment(1);
+}
+
+pub fn catalog_fetched(ok: bool) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(
+        "x5parser_catalogs_total",
+        "result" => if ok { "success" } else { "failure" }
+    )
+    .increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = ok;
+}
+
+pub fn products_inserted(count: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("x5parser_products_inserted_total").increment(count as u64);
+    #[cfg(not(feature = "metrics"))]
+    let _ = count;
+}
+
+pub fn catalog_page_fetched(elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!("x5parser_catalog_page_fetch_seconds").record(elapsed.as_secs_f64());
+    #[cfg(not(feature = "metrics"))]
+    let _ = elapsed;
+}
@@ -1,2 +1,6 @@
 pub mod models;
+pub mod perekrestok;
+pub mod pipeline;
 pub mod pyaterochka;
+pub mod raw_responses;
+pub mod retailer;
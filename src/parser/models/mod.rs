@@ -1,2 +1,2 @@
+pub mod perekrestok;
 pub mod pyaterochka;
-
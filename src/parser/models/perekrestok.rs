@@ -0,0 +1,190 @@
+use super::pyaterochka::{CatalogInfo, ProductInfo, StoreInfo, discount_percent};
+use serde::{Deserialize, Serialize};
+
+/// Ответы API Перекрестка завернуты в `content`
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Response<T> {
+    pub content: T,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ShopList {
+    #[serde(default)]
+    pub items: Vec<Shop>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Shop {
+    pub id: u64,
+
+    #[serde(default)]
+    pub title: String,
+
+    #[serde(default)]
+    pub address: String,
+
+    #[serde(default)]
+    pub city: Option<City>,
+
+    #[serde(default)]
+    pub location: Option<Location>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct City {
+    #[serde(default)]
+    pub name: String,
+}
+
+/// GeoJSON-точка: `coordinates` в порядке `[lon, lat]`
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Location {
+    #[serde(default)]
+    pub coordinates: Vec<f64>,
+}
+
+impl Shop {
+    pub fn lat_lon(&self) -> Option<(f64, f64)> {
+        match self.location.as_ref()?.coordinates.as_slice() {
+            [lon, lat] => Some((*lat, *lon)),
+            _ => None,
+        }
+    }
+}
+
+impl Into<StoreInfo> for Shop {
+    fn into(self) -> StoreInfo {
+        let address = if self.address.is_empty() {
+            self.title
+        } else {
+            self.address
+        };
+        return StoreInfo {
+            id: self.id.to_string(),
+            address,
+            city: self.city.map(|v| v.name).filter(|v| !v.trim().is_empty()),
+            region: None,
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductFeed {
+    #[serde(default)]
+    pub title: String,
+
+    #[serde(default)]
+    pub items: Vec<Product>,
+}
+
+impl CatalogInfo {
+    pub fn from_perekrestok_feed(feed: ProductFeed, id: String) -> Self {
+        Self {
+            id,
+            name: feed.title,
+            brand_list: Vec::new(),
+            products: feed
+                .items
+                .into_iter()
+                .map(Into::<ProductInfo>::into)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Product {
+    pub id: u64,
+
+    #[serde(default)]
+    pub title: String,
+
+    #[serde(default)]
+    pub master_data: Option<MasterData>,
+
+    #[serde(default)]
+    pub price_tag: Option<PriceTag>,
+
+    #[serde(default)]
+    pub image: Option<Image>,
+
+    /// Рейтинг в сотых долях, например 465 - это 4.65
+    #[serde(default)]
+    pub rating: Option<u32>,
+
+    #[serde(default)]
+    pub reviews_count: Option<u32>,
+
+    #[serde(default)]
+    pub is_available: Option<bool>,
+}
+
+/// Цены в копейках
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceTag {
+    /// Текущая цена (с учетом скидки)
+    #[serde(default)]
+    pub price: i64,
+
+    /// Цена до скидки
+    #[serde(default)]
+    pub gross_price: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MasterData {
+    #[serde(default)]
+    pub plu: Option<u64>,
+
+    #[serde(default)]
+    pub unit_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Image {
+    /// Шаблон ссылки, размер подставляется вместо `%s`
+    #[serde(default)]
+    pub crop_url_template: String,
+}
+
+impl Into<ProductInfo> for Product {
+    fn into(self) -> ProductInfo {
+        let price_tag = self.price_tag.unwrap_or_default();
+        let card_price = price_tag.price as f64 / 100.;
+        let price = price_tag
+            .gross_price
+            .filter(|v| *v > price_tag.price)
+            .map_or(card_price, |v| v as f64 / 100.);
+        let master_data = self.master_data.unwrap_or_default();
+        return ProductInfo {
+            id: master_data.plu.unwrap_or(self.id).to_string(),
+            name: self.title,
+            price,
+            card_price,
+            discount_percent: discount_percent(price, card_price),
+            price_per_unit: None,
+            rating: self.rating.map(|v| v as f64 / 100.),
+            rates_count: self.reviews_count,
+            image: self
+                .image
+                .map(|v| v.crop_url_template.replace("%s", "400x400"))
+                .filter(|v| !v.is_empty()),
+            property: None,
+            uom: master_data.unit_name.filter(|v| !v.is_empty()),
+            step: None,
+            stock_limit: None,
+            initial_weight_step: None,
+            min_weight: None,
+            is_available: self.is_available.unwrap_or(true),
+            labels: Vec::new(),
+            brand: None,
+        };
+    }
+}
@@ -1,4 +1,6 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use tracing::{debug, warn};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CatalogInfoWithTime {
@@ -27,18 +29,29 @@ impl CatalogInfo {
     pub fn from_catalog_with_id(mut c: Catalog, id: String) -> Self {
         let name = std::mem::take(&mut c.name);
         let filters = std::mem::take(&mut c.filters);
-        let brand_list = filters.into_iter()
+        let unknown_fields = c
+            .products
+            .iter()
+            .flat_map(|p| p.unknown_fields().keys())
+            .collect::<BTreeSet<_>>();
+        if !unknown_fields.is_empty() {
+            debug!(catalog_id = %id, "Unknown product fields: {unknown_fields:?}");
+        }
+        let brand_list = filters
+            .into_iter()
             .filter(|v| v.field_name == "brand")
             .map(|v| v.list_values.unwrap_or_default().all)
             .next()
             .unwrap_or_default();
-        let products = c.products.into_iter()
+        let products = c
+            .products
+            .into_iter()
             .map(Into::<ProductInfo>::into)
             .collect();
-        Self { 
+        Self {
             id: id,
-            name: name, 
-            brand_list: brand_list, 
+            name: name,
+            brand_list: brand_list,
             products: products,
         }
     }
@@ -50,10 +63,84 @@ pub struct ProductInfo {
     pub name: String,
     pub price: f64,
     pub card_price: f64,
+    pub discount_percent: Option<f64>,
+    pub price_per_unit: Option<f64>,
     pub rating: Option<f64>,
     pub rates_count: Option<u32>,
     pub image: Option<String>,
     pub property: Option<String>,
+    pub uom: Option<String>,
+    pub step: Option<String>,
+    pub stock_limit: Option<String>,
+    pub initial_weight_step: Option<String>,
+    pub min_weight: Option<String>,
+    pub is_available: bool,
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub brand: Option<String>,
+}
+
+/// Цена из строки вида "99.90", "99,90", "1 299,90 ₽", "1.299,90" или "99.90 руб.":
+/// десятичный разделитель - последний из `.`/`,` между цифрами, остальные разделители разрядов
+pub fn parse_price(v: &str) -> Option<f64> {
+    let start = v.find(|c: char| c.is_ascii_digit())?;
+    let end = v.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let number = &v[start..end];
+    let negative = v[..start].trim_end().ends_with('-');
+    let mut cleaned = String::from(if negative { "-" } else { "" });
+    match number.rfind(['.', ',']) {
+        Some(i) => {
+            cleaned.extend(number[..i].chars().filter(char::is_ascii_digit));
+            cleaned.push('.');
+            cleaned.extend(number[i..].chars().filter(char::is_ascii_digit));
+        }
+        None => cleaned.extend(number.chars().filter(char::is_ascii_digit)),
+    }
+
+    cleaned.parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+/// Размер скидки по карте в процентах, `None` если скидки нет
+pub fn discount_percent(price: f64, card_price: f64) -> Option<f64> {
+    if price > 0. && card_price < price {
+        Some((price - card_price) / price * 100.)
+    } else {
+        None
+    }
+}
+
+/// Byte position of the first whole-word occurrence of `word` in `haystack`.
+fn word_position(haystack: &str, word: &str) -> Option<usize> {
+    haystack
+        .match_indices(word)
+        .find(|(i, _)| {
+            let before = haystack[..*i].chars().next_back();
+            let after = haystack[i + word.len()..].chars().next();
+            !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+        })
+        .map(|(i, _)| i)
+}
+
+/// Longest brand from `brand_list` that occurs in `name` as whole words, ignoring case.
+/// Brands of the same length go by the first occurrence in `name`, then alphabetically,
+/// so the result does not depend on the order of `brand_list`.
+pub fn match_brand<'a>(name: &str, brand_list: &'a [String]) -> Option<&'a String> {
+    let name = name.to_lowercase();
+    brand_list
+        .iter()
+        .filter(|v| !v.trim().is_empty())
+        .filter_map(|v| {
+            let brand = v.trim().to_lowercase();
+            let position = word_position(&name, &brand)?;
+            Some((v, brand.chars().count(), position))
+        })
+        .min_by(|(a, a_len, a_pos), (b, b_len, b_pos)| {
+            b_len
+                .cmp(a_len)
+                .then(a_pos.cmp(b_pos))
+                .then_with(|| a.cmp(b))
+        })
+        .map(|(v, ..)| v)
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -61,6 +148,19 @@ pub struct StoreInfo {
     pub id: String,
     pub address: String,
     pub city: Option<String>,
+    /// Регион магазина. Пока известен только для городов федерального значения: в ответе
+    /// API поиска магазина поле региона не найдено, для остальных магазинов `None`
+    #[serde(default)]
+    pub region: Option<String>,
+}
+
+/// Города федерального значения сами являются регионом
+fn region_from_city(city: &str) -> Option<String> {
+    let city = city.trim().to_lowercase();
+    ["Москва", "Санкт-Петербург", "Севастополь"]
+        .into_iter()
+        .find(|v| v.to_lowercase() == city)
+        .map(String::from)
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -79,14 +179,22 @@ pub struct StoreApiInfo {
 
     #[serde(default)]
     pub has_24h_delivery: bool,
+
+    #[serde(default, alias = "latitude", alias = "store_lat")]
+    pub lat: Option<f64>,
+
+    #[serde(default, alias = "longitude", alias = "lng", alias = "store_lon")]
+    pub lon: Option<f64>,
 }
 
 impl Into<StoreInfo> for StoreApiInfo {
     fn into(self) -> StoreInfo {
+        let region = self.store_city.as_deref().and_then(region_from_city);
         return StoreInfo {
             id: self.sap_code,
             address: self.shop_address,
             city: self.store_city,
+            region,
         };
     }
 }
@@ -186,29 +294,114 @@ pub struct Product {
 
     /// Цена за штуку/единицу
     #[serde(default)]
-    pub price_piece_unit: Option<serde_json::Value>,
+    pub price_piece_unit: Option<PricePieceUnit>,
+
+    /// Бренд (если API его отдает)
+    #[serde(default)]
+    pub brand: Option<String>,
+
+    /// Поля, которых нет в модели (новые поля API)
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Product {
+    /// Поля ответа, не описанные в модели: по ним видно изменение формата API
+    pub fn unknown_fields(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.extra
+    }
 }
 
 impl Into<ProductInfo> for Product {
     fn into(self) -> ProductInfo {
-        let price = self.prices.regular.parse::<f64>().unwrap_or_default();
+        let price = parse_price(&self.prices.regular).unwrap_or_else(|| {
+            warn!(
+                plu = self.plu,
+                "Failed to parse price {:?}", self.prices.regular
+            );
+            0.
+        });
+        let card_price = match self.prices.discount.as_deref() {
+            Some(discount) => parse_price(discount).unwrap_or_else(|| {
+                warn!(
+                    plu = self.plu,
+                    "Failed to parse discount price {discount:?}"
+                );
+                price
+            }),
+            None => price,
+        };
         return ProductInfo {
             id: self.plu.to_string(),
             name: self.name,
             price: price,
-            card_price: if let Some(discount) = self.prices.discount {
-                discount.parse::<f64>().unwrap_or(price)
-            } else {
-                price
-            },
+            card_price: card_price,
+            discount_percent: discount_percent(price, card_price),
+            price_per_unit: self
+                .price_piece_unit
+                .as_ref()
+                .and_then(PricePieceUnit::price),
             rating: self.rating.as_ref().and_then(|v| Some(v.rating_average)),
-            rates_count: self.rating.and_then(|v| Some(v.rates_count)), 
+            rates_count: self.rating.and_then(|v| Some(v.rates_count)),
             image: self.image_links.normal.get(0).cloned(),
             property: self.property_clarification,
+            uom: Some(self.uom).filter(|v| !v.is_empty()),
+            step: Some(self.step).filter(|v| !v.is_empty()),
+            stock_limit: self.stock_limit,
+            initial_weight_step: self.initial_weight_step,
+            min_weight: self.min_weight,
+            is_available: self.is_available,
+            labels: self
+                .labels
+                .unwrap_or_default()
+                .into_iter()
+                .map(|v| v.label)
+                .filter(|v| !v.is_empty())
+                .collect(),
+            brand: self.brand.filter(|v| !v.trim().is_empty()),
         };
     }
 }
 
+/// Цена за единицу измерения (для весового товара - за 1 кг).
+/// API отдает ее то объектом, то строкой или числом
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PricePieceUnit {
+    Number(f64),
+    Text(String),
+    Info(PricePieceUnitInfo),
+    Other(serde_json::Value),
+}
+
+impl PricePieceUnit {
+    pub fn price(&self) -> Option<f64> {
+        match self {
+            PricePieceUnit::Number(v) => Some(*v),
+            PricePieceUnit::Text(v) => parse_price(v),
+            PricePieceUnit::Info(v) => v.price.as_ref().and_then(|v| match v {
+                serde_json::Value::Number(v) => v.as_f64(),
+                serde_json::Value::String(v) => parse_price(v),
+                _ => None,
+            }),
+            PricePieceUnit::Other(_) => None,
+        }
+        .filter(|v| v.is_finite() && *v > 0.)
+    }
+}
+
+/// Цена за единицу в виде объекта
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct PricePieceUnitInfo {
+    /// Цена за единицу (строка или число)
+    #[serde(default, alias = "regular")]
+    pub price: Option<serde_json::Value>,
+
+    /// Единица, к которой относится цена
+    #[serde(default)]
+    pub uom: Option<String>,
+}
+
 /// Ссылки на изображения
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct ImageLinks {
@@ -260,3 +453,226 @@ pub struct Label {
     #[serde(default)]
     pub text_color: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn product_info(json: serde_json::Value) -> ProductInfo {
+        serde_json::from_value::<Product>(json).unwrap().into()
+    }
+
+    #[test]
+    fn product_without_discount() {
+        let p = product_info(serde_json::json!({
+            "plu": 3398584,
+            "name": "Молоко Простоквашино 2,5% 930мл",
+            "image_links": {"small": ["s.jpg"], "normal": ["n.jpg"]},
+            "uom": "шт",
+            "step": "1",
+            "rating": {"rating_average": 4.8, "rates_count": 120},
+            "prices": {"regular": "99.90", "discount": null},
+            "labels": [{"label": "Новинка"}, {"label": ""}],
+            "is_available": true
+        }));
+        assert_eq!(p.id, "3398584");
+        assert_eq!(p.price, 99.9);
+        assert_eq!(p.card_price, 99.9);
+        assert_eq!(p.discount_percent, None);
+        assert_eq!(p.rating, Some(4.8));
+        assert_eq!(p.rates_count, Some(120));
+        assert_eq!(p.image.as_deref(), Some("n.jpg"));
+        assert_eq!(p.uom.as_deref(), Some("шт"));
+        assert_eq!(p.labels, vec!["Новинка".to_string()]);
+        assert!(p.is_available);
+    }
+
+    #[test]
+    fn product_with_discount() {
+        let p = product_info(serde_json::json!({
+            "plu": 1,
+            "prices": {"regular": "200", "discount": "150,00"}
+        }));
+        assert_eq!(p.price, 200.);
+        assert_eq!(p.card_price, 150.);
+        assert_eq!(p.discount_percent, Some(25.));
+    }
+
+    #[test]
+    fn product_with_unparseable_price() {
+        let p = product_info(serde_json::json!({
+            "plu": 2,
+            "prices": {"regular": "нет в наличии", "discount": "???"}
+        }));
+        assert_eq!(p.price, 0.);
+        assert_eq!(p.card_price, 0.);
+        assert_eq!(p.discount_percent, None);
+    }
+
+    #[test]
+    fn product_with_zero_price() {
+        let p = product_info(serde_json::json!({
+            "plu": 3,
+            "prices": {"regular": "0.00"}
+        }));
+        assert_eq!(p.price, 0.);
+        assert_eq!(p.card_price, 0.);
+        assert_eq!(p.discount_percent, None);
+    }
+
+    #[test]
+    fn product_with_empty_images_and_no_rating() {
+        let p = product_info(serde_json::json!({
+            "plu": 4,
+            "image_links": {"small": [], "normal": []},
+            "prices": {"regular": "49.99"}
+        }));
+        assert_eq!(p.image, None);
+        assert_eq!(p.rating, None);
+        assert_eq!(p.rates_count, None);
+        assert_eq!(p.uom, None);
+        assert!(p.labels.is_empty());
+    }
+
+    #[test]
+    fn weighted_product() {
+        let p = product_info(serde_json::json!({
+            "plu": 5,
+            "name": "Бананы",
+            "uom": "кг",
+            "step": "0.1",
+            "initial_weight_step": "0.5",
+            "min_weight": "0.3",
+            "prices": {"regular": "139.99"},
+            "price_piece_unit": {"price": "139.99", "uom": "кг"}
+        }));
+        assert_eq!(p.uom.as_deref(), Some("кг"));
+        assert_eq!(p.step.as_deref(), Some("0.1"));
+        assert_eq!(p.initial_weight_step.as_deref(), Some("0.5"));
+        assert_eq!(p.min_weight.as_deref(), Some("0.3"));
+        assert_eq!(p.price_per_unit, Some(139.99));
+    }
+
+    #[test]
+    fn price_piece_unit_payloads() {
+        let unit = |json: serde_json::Value| {
+            serde_json::from_value::<PricePieceUnit>(json)
+                .unwrap()
+                .price()
+        };
+        let info = serde_json::json!({"price": "459.90", "uom": "кг"});
+        assert!(matches!(
+            serde_json::from_value::<PricePieceUnit>(info.clone()).unwrap(),
+            PricePieceUnit::Info(PricePieceUnitInfo { uom: Some(ref v), .. }) if v == "кг"
+        ));
+        assert_eq!(unit(info), Some(459.9));
+        assert_eq!(
+            unit(serde_json::json!({"regular": 89.5, "uom": "л"})),
+            Some(89.5)
+        );
+        assert_eq!(unit(serde_json::json!(" 99,50 ")), Some(99.5));
+        assert_eq!(unit(serde_json::json!("1 099,00")), Some(1099.));
+        assert_eq!(unit(serde_json::json!(129.99)), Some(129.99));
+        assert_eq!(unit(serde_json::json!({"uom": "кг"})), None);
+        assert_eq!(unit(serde_json::json!("0")), None);
+        assert_eq!(unit(serde_json::json!(true)), None);
+    }
+
+    #[test]
+    fn product_with_null_price_piece_unit() {
+        let p = product_info(serde_json::json!({
+            "plu": 6,
+            "prices": {"regular": "10"},
+            "price_piece_unit": null
+        }));
+        assert_eq!(p.price_per_unit, None);
+    }
+
+    fn store_info(json: serde_json::Value) -> StoreInfo {
+        serde_json::from_value::<StoreApiInfo>(json).unwrap().into()
+    }
+
+    #[test]
+    fn store_region_from_federal_city() {
+        let store = store_info(serde_json::json!({
+            "sap_code": "35XY",
+            "shop_address": "ул. Ленина, 1",
+            "store_city": " москва "
+        }));
+        assert_eq!(store.id, "35XY");
+        assert_eq!(store.region.as_deref(), Some("Москва"));
+
+        let store = store_info(serde_json::json!({"sap_code": "1", "store_city": "Казань"}));
+        assert_eq!(store.region, None);
+        assert_eq!(
+            store_info(serde_json::json!({"sap_code": "2"})).region,
+            None
+        );
+    }
+
+    fn brands(v: &[&str]) -> Vec<String> {
+        v.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn match_brand_prefers_longest_overlapping_brand() {
+        let list = brands(&["Простоквашино", "Простоквашино Отборное", "Домик в деревне"]);
+        let brand = match_brand("Молоко Простоквашино Отборное 3,4-4,5% 930мл", &list);
+        assert_eq!(brand.map(String::as_str), Some("Простоквашино Отборное"));
+        let brand = match_brand("Кефир Простоквашино 1% 930мл", &list);
+        assert_eq!(brand.map(String::as_str), Some("Простоквашино"));
+    }
+
+    #[test]
+    fn match_brand_ignores_case() {
+        let list = brands(&["Домик в деревне"]);
+        let brand = match_brand("МОЛОКО ДОМИК В ДЕРЕВНЕ 2,5%", &list);
+        assert_eq!(brand.map(String::as_str), Some("Домик в деревне"));
+    }
+
+    #[test]
+    fn match_brand_requires_whole_words() {
+        let list = brands(&["Село", "Простоквашино"]);
+        assert_eq!(match_brand("Молоко Селочка 2,5%", &list), None);
+        assert_eq!(match_brand("Простоквашинское масло", &list), None);
+    }
+
+    #[test]
+    fn match_brand_breaks_ties_by_position_then_name() {
+        // Brands of the same length: the one that comes first in the name wins
+        for list in [
+            brands(&["Viola", "Агуша", "  "]),
+            brands(&["Агуша", "Viola"]),
+        ] {
+            let brand = match_brand("Сырок Агуша Viola", &list);
+            assert_eq!(brand.map(String::as_str), Some("Агуша"));
+            let brand = match_brand("Сырок Viola с наполнителем Агуша", &list);
+            assert_eq!(brand.map(String::as_str), Some("Viola"));
+        }
+        // Same words at the same position: alphabetical order
+        for list in [brands(&["Viola", "VIOLA"]), brands(&["VIOLA", "Viola"])] {
+            let brand = match_brand("Сыр плавленый Viola", &list);
+            assert_eq!(brand.map(String::as_str), Some("VIOLA"));
+        }
+    }
+
+    #[test]
+    fn parse_price_formats() {
+        assert_eq!(parse_price("99.90"), Some(99.9));
+        assert_eq!(parse_price("99,90"), Some(99.9));
+        assert_eq!(parse_price("1 299,90 ₽"), Some(1299.9));
+        assert_eq!(parse_price("1\u{a0}299,90 ₽"), Some(1299.9));
+        assert_eq!(parse_price("1.299,90"), Some(1299.9));
+        assert_eq!(parse_price("1,299.90"), Some(1299.9));
+        assert_eq!(parse_price("99.90 руб."), Some(99.9));
+        assert_eq!(parse_price("от 150 ₽"), Some(150.));
+        assert_eq!(parse_price("-5,5"), Some(-5.5));
+    }
+
+    #[test]
+    fn parse_price_rejects_text_without_digits() {
+        assert_eq!(parse_price(""), None);
+        assert_eq!(parse_price("руб."), None);
+        assert_eq!(parse_price(".,"), None);
+    }
+}
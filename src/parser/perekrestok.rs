@@ -0,0 +1,290 @@
+use crate::error::{Error, Result};
+use crate::parser::models::perekrestok as models;
+use crate::parser::models::pyaterochka::CatalogInfo;
+use crate::parser::retailer::{CatalogFilter, CatalogPage, Retailer, StoreLookup};
+use tracing::warn;
+
+pub const MAX_CATALOG_API_LIMIT: u16 = 100;
+
+pub const HOME_PAGE_URL: &str = "https://www.perekrestok.ru/";
+
+/// Customer API of the perekrestok.ru web client (version 1.4.1.0): `shop/points` for the store
+/// lookup and `catalog/product/feed` for catalog pages, response shapes in `models::perekrestok`.
+/// They follow the requests of the site, but no recorded response is checked in: the tests use
+/// hand-written payloads of the same shape. Save real ones with `raw_response_dir` and check them
+/// with `--replay` when the site changes.
+const API_URL: &str = "https://www.perekrestok.ru/api/customer/1.4.1.0";
+
+pub const MAIN_CATALOG_LIST: [Catalog; 10] = [
+    Catalog::GotovayaEda,
+    Catalog::OvoshchiIFrukty,
+    Catalog::MolokoSyrYaytsa,
+    Catalog::KhlebIVypechka,
+    Catalog::MyasoIPtitsa,
+    Catalog::RybaIMoreprodukty,
+    Catalog::Sladosti,
+    Catalog::Bakaleya,
+    Catalog::ZamorozhennyeProdukty,
+    Catalog::Napitki,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Catalog {
+    GotovayaEda,
+    OvoshchiIFrukty,
+    MolokoSyrYaytsa,
+    KhlebIVypechka,
+    MyasoIPtitsa,
+    RybaIMoreprodukty,
+    Sladosti,
+    Bakaleya,
+    ZamorozhennyeProdukty,
+    Napitki,
+}
+
+impl Catalog {
+    pub fn as_catalog_id(&self) -> &'static str {
+        match self {
+            Catalog::GotovayaEda => "1222",
+            Catalog::OvoshchiIFrukty => "153",
+            Catalog::MolokoSyrYaytsa => "113",
+            Catalog::KhlebIVypechka => "187",
+            Catalog::MyasoIPtitsa => "150",
+            Catalog::RybaIMoreprodukty => "116",
+            Catalog::Sladosti => "129",
+            Catalog::Bakaleya => "200",
+            Catalog::ZamorozhennyeProdukty => "121",
+            Catalog::Napitki => "145",
+        }
+    }
+
+    /// Название категории на сайте
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Catalog::GotovayaEda => "Готовая еда",
+            Catalog::OvoshchiIFrukty => "Овощи и фрукты",
+            Catalog::MolokoSyrYaytsa => "Молоко, сыр, яйца",
+            Catalog::KhlebIVypechka => "Хлеб и выпечка",
+            Catalog::MyasoIPtitsa => "Мясо и птица",
+            Catalog::RybaIMoreprodukty => "Рыба и морепродукты",
+            Catalog::Sladosti => "Сладости",
+            Catalog::Bakaleya => "Бакалея",
+            Catalog::ZamorozhennyeProdukty => "Замороженные продукты",
+            Catalog::Napitki => "Напитки",
+        }
+    }
+
+    pub fn all() -> impl Iterator<Item = Catalog> {
+        MAIN_CATALOG_LIST.into_iter()
+    }
+
+    pub fn from_id_or_name(v: &str) -> Option<Catalog> {
+        Catalog::all().find(|c| c.as_catalog_id() == v || format!("{c:?}").eq_ignore_ascii_case(v))
+    }
+
+    pub fn as_api_url(&self, shop_id: &str, page: CatalogPage) -> String {
+        let order = match page.filter {
+            CatalogFilter::Default => "",
+            CatalogFilter::PriceDesc => "&orderBy=price&orderDirection=desc",
+            CatalogFilter::PriceAsc => "&orderBy=price&orderDirection=asc",
+        };
+        format!(
+            "{API_URL}/catalog/product/feed?shopId={shop_id}&categoryId={catalog_id}&page={n}&perPage={limit}{order}",
+            catalog_id = self.as_catalog_id(),
+            n = page.offset / page.limit.max(1) as u32 + 1,
+            limit = page.limit,
+        )
+    }
+}
+
+pub fn selected_catalogs(catalogs: Option<&[String]>) -> Vec<Catalog> {
+    let Some(catalogs) = catalogs else {
+        return Catalog::all().collect();
+    };
+    catalogs
+        .iter()
+        .filter_map(|v| {
+            let catalog = Catalog::from_id_or_name(v.trim());
+            if catalog.is_none() {
+                warn!("Unknown catalog {v:?}, skipping");
+            }
+            catalog
+        })
+        .collect()
+}
+
+pub fn store_from_coord_url(lat: f32, lon: f32) -> String {
+    format!("{API_URL}/shop/points?lat={lat}&lng={lon}&limit=1")
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Perekrestok;
+
+impl Retailer for Perekrestok {
+    type Catalog = Catalog;
+
+    fn name(&self) -> &'static str {
+        "perekrestok"
+    }
+
+    fn home_page_url(&self) -> &'static str {
+        HOME_PAGE_URL
+    }
+
+    fn cookie_domain(&self) -> &'static str {
+        "perekrestok.ru"
+    }
+
+    fn max_catalog_limit(&self) -> u16 {
+        MAX_CATALOG_API_LIMIT
+    }
+
+    fn catalog_id(&self, c: Catalog) -> &'static str {
+        c.as_catalog_id()
+    }
+
+    fn catalog_name(&self, c: Catalog) -> &'static str {
+        c.display_name()
+    }
+
+    fn catalogs(&self, selected: Option<&[String]>) -> Vec<Catalog> {
+        selected_catalogs(selected)
+    }
+
+    fn store_lookup_url(&self, lat: f32, lon: f32) -> String {
+        store_from_coord_url(lat, lon)
+    }
+
+    fn parse_store(&self, body: &str) -> Result<StoreLookup> {
+        let shops = serde_json::from_str::<models::Response<models::ShopList>>(body)?;
+        let Some(shop) = shops.content.items.into_iter().next() else {
+            return Err(Error::StoreNotFound);
+        };
+        let (lat, lon) = shop.lat_lon().unzip();
+
+        Ok(StoreLookup {
+            info: shop.into(),
+            lat,
+            lon,
+        })
+    }
+
+    fn catalog_url(&self, store_id: &str, c: Catalog, page: CatalogPage) -> String {
+        c.as_api_url(store_id, page)
+    }
+
+    fn parse_catalog(&self, body: &str, c: Catalog) -> Result<CatalogInfo> {
+        let feed = serde_json::from_str::<models::Response<models::ProductFeed>>(body)?;
+
+        Ok(CatalogInfo::from_perekrestok_feed(
+            feed.content,
+            c.as_catalog_id().into(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHOP_POINTS: &str = r#"{
+        "content": {
+            "items": [{
+                "id": 5412,
+                "title": "Перекресток",
+                "address": "Москва, Ленинградский пр-т, 62А",
+                "city": {"id": 81, "name": "Москва"},
+                "location": {"type": "Point", "coordinates": [37.5327, 55.8031]}
+            }]
+        }
+    }"#;
+
+    const PRODUCT_FEED: &str = r#"{
+        "content": {
+            "title": "Молоко, сыр, яйца",
+            "items": [
+                {
+                    "id": 48760,
+                    "title": "Молоко Простоквашино 3,2% 930мл",
+                    "masterData": {"plu": 3041217, "unitName": "шт"},
+                    "priceTag": {"price": 8999, "grossPrice": 10999},
+                    "image": {"cropUrlTemplate": "https://cdn-img.perekrestok.ru/i/%s/xdelivery/files/ab/cd.jpg"},
+                    "rating": 465,
+                    "reviewsCount": 87,
+                    "isAvailable": true
+                },
+                {
+                    "id": 52013,
+                    "title": "Сыр Ламбер 50% 230г",
+                    "priceTag": {"price": 34999, "grossPrice": 34999},
+                    "isAvailable": false
+                }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn parse_store_from_shop_points() {
+        let store = Perekrestok.parse_store(SHOP_POINTS).unwrap();
+        assert_eq!(store.info.id, "5412");
+        assert_eq!(store.info.address, "Москва, Ленинградский пр-т, 62А");
+        assert_eq!(store.info.city.as_deref(), Some("Москва"));
+        // GeoJSON keeps `[lon, lat]`
+        assert_eq!((store.lat, store.lon), (Some(55.8031), Some(37.5327)));
+    }
+
+    #[test]
+    fn parse_store_without_shops() {
+        let body = r#"{"content": {"items": []}}"#;
+        assert!(matches!(
+            Perekrestok.parse_store(body),
+            Err(Error::StoreNotFound)
+        ));
+    }
+
+    #[test]
+    fn parse_catalog_from_product_feed() {
+        let catalog = Perekrestok
+            .parse_catalog(PRODUCT_FEED, Catalog::MolokoSyrYaytsa)
+            .unwrap();
+        assert_eq!(catalog.id, "113");
+        assert_eq!(catalog.name, "Молоко, сыр, яйца");
+        let [milk, cheese] = catalog.products.as_slice() else {
+            panic!("expected two products");
+        };
+
+        assert_eq!(milk.id, "3041217");
+        // Prices come in kopecks
+        assert_eq!((milk.price, milk.card_price), (109.99, 89.99));
+        assert!(
+            milk.discount_percent
+                .is_some_and(|v| (v - 18.18).abs() < 0.01)
+        );
+        // Rating comes in hundredths
+        assert_eq!(milk.rating, Some(4.65));
+        assert_eq!(milk.rates_count, Some(87));
+        assert_eq!(milk.uom.as_deref(), Some("шт"));
+        assert_eq!(
+            milk.image.as_deref(),
+            Some("https://cdn-img.perekrestok.ru/i/400x400/xdelivery/files/ab/cd.jpg")
+        );
+        assert!(milk.is_available);
+
+        // No `masterData`: the product id is used
+        assert_eq!(cheese.id, "52013");
+        assert_eq!((cheese.price, cheese.card_price), (349.99, 349.99));
+        assert_eq!(cheese.discount_percent, None);
+        assert_eq!((cheese.rating, cheese.image.as_deref()), (None, None));
+        assert!(!cheese.is_available);
+    }
+
+    #[test]
+    fn from_id_or_name_round_trips_every_catalog() {
+        assert_eq!(Catalog::all().count(), MAIN_CATALOG_LIST.len());
+        for c in Catalog::all() {
+            assert_eq!(Catalog::from_id_or_name(c.as_catalog_id()), Some(c));
+            assert_eq!(Catalog::from_id_or_name(&format!("{c:?}")), Some(c));
+        }
+        assert_eq!(Catalog::from_id_or_name("251C12887"), None);
+    }
+}
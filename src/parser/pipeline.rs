@@ -0,0 +1,1966 @@
+use crate::browser_utils::{self as bu, OpenPageParams};
+use crate::config::Config;
+use crate::db;
+use crate::error::{Error, Result};
+use crate::images::ImageDownloader;
+use crate::json_output::JsonOutput;
+use crate::metrics;
+use crate::parser::models::pyaterochka as models;
+use crate::parser::raw_responses::RawResponseStore;
+use crate::parser::retailer::{
+    CATALOG_FILTERS_LIST, CatalogFilter, CatalogPage, Retailer, StoreLookup,
+};
+use crate::status::RunStatus;
+use chromiumoxide::cdp::browser_protocol::network::Cookie;
+use chromiumoxide::{Browser, browser::HeadlessMode};
+use rand::Rng;
+use rand::seq::{IndexedRandom, SliceRandom};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{Instrument, error, info, info_span, warn};
+
+const OPEN_PAGE_RETRIES: u32 = 3;
+/// Pause between catalog requests when `sleep_millis_for_each_catalog` is not set
+const DEFAULT_CATALOG_SLEEP_MILLIS: u64 = 700;
+pub(crate) const DEFAULT_MIN_CATALOG_PRODUCTS: usize = 1;
+pub(crate) const DEFAULT_MAX_EMPTY_PRODUCTS_RATIO: f64 = 0.5;
+/// `db` state key with the index of the last stored target of the current pass
+const RESUME_CURSOR_KEY: &str = "resume_cursor";
+const OPEN_PAGE_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+pub(crate) fn jittered_delay(base_millis: u64, jitter_millis: u64) -> Duration {
+    let jitter = if jitter_millis > 0 {
+        rand::rng().random_range(0..=jitter_millis)
+    } else {
+        0
+    };
+    Duration::from_millis(base_millis + jitter)
+}
+
+/// `tokio::time::sleep` cut short by the first Ctrl+C; `false` if it was.
+async fn sleep_unless_shutdown(
+    delay: Duration,
+    shutdown: &tokio::sync::watch::Receiver<bool>,
+) -> bool {
+    let mut shutdown = shutdown.clone();
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => true,
+        _ = shutdown.wait_for(|v| *v) => false,
+    }
+}
+
+/// Great-circle distance between two points in kilometers.
+pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.;
+    let (d_lat, d_lon) = ((lat2 - lat1).to_radians(), (lon2 - lon1).to_radians());
+    let a = (d_lat / 2.).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.).sin().powi(2);
+
+    2. * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+pub const DEFAULT_GRID_STEP: f32 = 0.01;
+
+pub fn generate_coord_grid(
+    min_lat: f32,
+    min_lon: f32,
+    max_lat: f32,
+    max_lon: f32,
+    step: f32,
+) -> Vec<[f32; 2]> {
+    if step.is_nan() || step <= 0. || min_lat > max_lat || min_lon > max_lon {
+        return Vec::new();
+    }
+    let (min_lat, min_lon, step) = (min_lat as f64, min_lon as f64, step as f64);
+    let lat_steps = ((max_lat as f64 - min_lat) / step + 1e-9).floor() as usize;
+    let lon_steps = ((max_lon as f64 - min_lon) / step + 1e-9).floor() as usize;
+    let mut grid = Vec::with_capacity((lat_steps + 1) * (lon_steps + 1));
+    for i in 0..=lat_steps {
+        for j in 0..=lon_steps {
+            grid.push([
+                (min_lat + i as f64 * step) as f32,
+                (min_lon + j as f64 * step) as f32,
+            ]);
+        }
+    }
+
+    grid
+}
+
+fn valid_coord(lat: f64, lon: f64) -> Option<[f32; 2]> {
+    if !lat.is_finite() || !lon.is_finite() || lat.abs() > 90. || lon.abs() > 180. {
+        return None;
+    }
+
+    Some([lat as f32, lon as f32])
+}
+
+fn parse_coord(v: &serde_json::Value) -> Option<[f32; 2]> {
+    let [lat, lon] = v.as_array()?.as_slice() else {
+        return None;
+    };
+    valid_coord(lat.as_f64()?, lon.as_f64()?)
+}
+
+fn parse_csv_row(line: &str) -> Option<[f32; 2]> {
+    let mut columns = line.split([',', ';']).map(|v| v.trim().trim_matches('"'));
+    let (lat, lon) = (columns.next()?.parse().ok()?, columns.next()?.parse().ok()?);
+    valid_coord(lat, lon)
+}
+
+/// `lat,lon` rows (`;` is accepted too); a first row with letters is treated as a header.
+fn parse_csv_coords(data: &str) -> Vec<Option<[f32; 2]>> {
+    let mut rows = data.lines().filter(|v| !v.trim().is_empty()).peekable();
+    if rows
+        .peek()
+        .is_some_and(|v| v.chars().any(char::is_alphabetic))
+    {
+        rows.next();
+    }
+    rows.map(parse_csv_row).collect()
+}
+
+/// Coordinates from a JSON array of `[lat, lon]` or from a `.csv` file, in file order
+/// or shuffled to spread the load.
+pub async fn read_store_coords(path: &str, shuffle: bool) -> Result<Vec<[f32; 2]>> {
+    let coords_data = tokio::fs::read_to_string(path).await?;
+    let is_csv = std::path::Path::new(path)
+        .extension()
+        .is_some_and(|v| v.eq_ignore_ascii_case("csv"));
+    let raw_coords = if is_csv {
+        parse_csv_coords(&coords_data)
+    } else {
+        serde_json::from_str::<Vec<serde_json::Value>>(&coords_data)
+            .map_err(|e| Error::from(e).with_context(format!("coordinates ({path})")))?
+            .iter()
+            .map(parse_coord)
+            .collect()
+    };
+    let mut stores_coord = Vec::with_capacity(raw_coords.len());
+    let mut skipped = Vec::new();
+    for (i, v) in raw_coords.into_iter().enumerate() {
+        match v {
+            Some(coord) => stores_coord.push(coord),
+            None => skipped.push(i),
+        }
+    }
+    if !skipped.is_empty() {
+        warn!(
+            "Skipped {} malformed coordinates in {path}: {skipped:?}",
+            skipped.len()
+        );
+    }
+    if stores_coord.is_empty() {
+        return Err(Error::NoValidCoordinates {
+            path: path.to_string(),
+        });
+    }
+    if shuffle {
+        stores_coord.shuffle(&mut rand::rng());
+    }
+
+    Ok(stores_coord)
+}
+
+async fn set_cookies_from_path(b: &Browser, path: &str, domain: &str) -> Result<()> {
+    if !std::fs::exists(path).unwrap_or(false) {
+        return Ok(());
+    }
+    let cookies_json = tokio::fs::read_to_string(path).await?;
+    let cookies = serde_json::from_str::<Vec<Cookie>>(&cookies_json)?;
+    set_cookies(b, cookies, domain).await
+}
+
+/// Sets the cookies of `domain`, skipping expired ones (Chrome may reject the whole batch).
+async fn set_cookies(b: &Browser, cookies: Vec<Cookie>, domain: &str) -> Result<()> {
+    let total = cookies.len();
+    let now = chrono::Utc::now().timestamp() as f64;
+    let cookies_param = cookies
+        .into_iter()
+        .filter(|c| c.domain.trim_start_matches('.').ends_with(domain))
+        .filter(|c| !bu::cookie_is_expired(c, now))
+        .map(bu::cookie_into_param)
+        .collect::<Vec<_>>();
+    info!(
+        "Applying {} of {total} cookies, skipped {} (expired or not {domain})",
+        cookies_param.len(),
+        total - cookies_param.len()
+    );
+    if !cookies_param.is_empty() {
+        b.set_cookies(cookies_param).await?;
+    }
+    Ok(())
+}
+
+/// Element of the home page waited for before collecting cookies
+const DEFAULT_COOKIE_WAIT_SELECTOR: &str = "footer";
+
+/// How long `update_cookies_with_browser` waits for the home page
+struct CookieWait<'a> {
+    /// Element showing that the home page is loaded, a fixed 5s sleep is used if it does not
+    /// appear within `selector_timeout` (or the selector is empty)
+    selector: &'a str,
+    selector_timeout: Duration,
+    max_wait: Duration,
+}
+
+/// The wait ends early on Ctrl+C, the cookies collected so far are saved.
+async fn update_cookies_with_browser(
+    b: &Browser,
+    home_page_url: &str,
+    cookies_store_path: &str,
+    user_agent: Option<&str>,
+    proxy_auth: Option<&bu::ProxyCredentials>,
+    wait: CookieWait<'_>,
+    shutdown: &tokio::sync::watch::Receiver<bool>,
+) -> Result<Vec<Cookie>> {
+    let page = bu::open_page(
+        &b,
+        &bu::OpenPageParams {
+            url: home_page_url,
+            user_agent,
+            proxy_auth,
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let CookieWait {
+        selector,
+        selector_timeout,
+        max_wait,
+    } = wait;
+    let deadline = tokio::time::Instant::now() + max_wait;
+    let waited = !selector.is_empty()
+        && match bu::wait_for_selector(&page, selector, selector_timeout.min(max_wait)).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("{selector:?} did not appear on the home page: {e}, waiting 5s instead");
+                false
+            }
+        };
+    let mut interrupted =
+        !waited && !sleep_unless_shutdown(Duration::from_secs(5).min(max_wait), shutdown).await;
+
+    while !interrupted && let Some(url) = page.url().await? {
+        if url.as_str() == home_page_url {
+            break;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            warn!("Home page was not reached in {max_wait:?}, saving current cookies");
+            break;
+        }
+        interrupted = !sleep_unless_shutdown(Duration::from_secs(1), shutdown).await;
+    }
+
+    let cookies = b.get_cookies().await?;
+    let cookies_json = serde_json::ser::to_string_pretty(&cookies)?;
+    tokio::fs::write(cookies_store_path, cookies_json).await?;
+
+    let _ = page.close().await;
+
+    Ok(cookies)
+}
+
+fn cookies_store_path<R: Retailer>(retailer: &R, pc: &ParseConfig) -> String {
+    pc.cookies_store_path
+        .clone()
+        .unwrap_or_else(|| format!("{}_cookies", retailer.name()))
+}
+
+/// Stored cookies of `domain` are fresh if none of the persistent ones expires within
+/// `min_validity`. Missing or unreadable file is never fresh.
+async fn stored_cookies_fresh(path: &str, domain: &str, min_validity: Duration) -> bool {
+    let Ok(cookies_json) = tokio::fs::read_to_string(path).await else {
+        return false;
+    };
+    let Ok(cookies) = serde_json::from_str::<Vec<Cookie>>(&cookies_json) else {
+        warn!("Failed to parse cookies file {path}, refreshing cookies");
+        return false;
+    };
+    let valid_until = chrono::Utc::now().timestamp() as f64 + min_validity.as_secs_f64();
+    let mut expires = cookies
+        .iter()
+        .filter(|c| c.domain.trim_start_matches('.').ends_with(domain))
+        .filter(|c| !c.session && c.expires > 0.)
+        .map(|c| c.expires)
+        .peekable();
+    expires.peek().is_some() && expires.all(|v| v > valid_until)
+}
+
+/// Refreshes cookies with a visible browser unless the stored ones are valid for at least
+/// `cookie_min_validity_secs`.
+async fn ensure_fresh_cookies<R: Retailer>(
+    retailer: &R,
+    pc: &ParseConfig,
+    shutdown: &tokio::sync::watch::Receiver<bool>,
+) -> Result<()> {
+    if let Some(secs) = pc.cookie_min_validity_secs {
+        let path = cookies_store_path(retailer, pc);
+        if stored_cookies_fresh(&path, retailer.cookie_domain(), Duration::from_secs(secs)).await {
+            info!("Stored cookies in {path} are valid for more than {secs}s, skipping refresh");
+            return Ok(());
+        }
+    }
+    update_cookies(retailer, pc, shutdown).await?;
+    Ok(())
+}
+
+async fn update_cookies<R: Retailer>(
+    retailer: &R,
+    pc: &ParseConfig,
+    shutdown: &tokio::sync::watch::Receiver<bool>,
+) -> Result<Vec<Cookie>> {
+    let headless_mode = if pc.cookie_refresh_headless.unwrap_or(false) {
+        HeadlessMode::New
+    } else {
+        HeadlessMode::False
+    };
+    let mut b = bu::launch_browser(&launch_params(pc, headless_mode, pc.proxy.as_deref())).await?;
+
+    if let Some(path) = pc.cookies_store_path.as_deref() {
+        set_cookies_from_path(&b, path, retailer.cookie_domain()).await?;
+    }
+
+    let proxy_auth = pc
+        .proxy
+        .as_deref()
+        .and_then(|v| bu::split_proxy_credentials(v).1);
+    let cookies = update_cookies_with_browser(
+        &b,
+        retailer.home_page_url(),
+        &cookies_store_path(retailer, pc),
+        pc.user_agent.as_deref(),
+        proxy_auth.as_ref(),
+        CookieWait {
+            selector: pc
+                .cookie_wait_selector
+                .as_deref()
+                .unwrap_or(DEFAULT_COOKIE_WAIT_SELECTOR),
+            selector_timeout: Duration::from_secs(pc.cookie_page_timeout_secs.unwrap_or(30)),
+            max_wait: Duration::from_secs(pc.cookie_wait_timeout_secs.unwrap_or(120)),
+        },
+        shutdown,
+    )
+    .await?;
+
+    bu::close_browser(&mut b).await;
+
+    Ok(cookies)
+}
+
+/// Global request budget: a permit is taken before every store lookup and catalog page
+/// request, permits are returned one by one every `60s / requests_per_minute`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    permits: Arc<Semaphore>,
+}
+
+impl RateLimiter {
+    /// Must be called inside the tokio runtime, the refill task stops with the limiter.
+    pub fn new(requests_per_minute: u32) -> Self {
+        let permits = Arc::new(Semaphore::new(1));
+        let period = Duration::from_secs(60) / requests_per_minute.max(1);
+        let weak = Arc::downgrade(&permits);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(permits) = weak.upgrade() else {
+                    break;
+                };
+                if permits.available_permits() == 0 {
+                    permits.add_permits(1);
+                }
+            }
+        });
+
+        Self { permits }
+    }
+
+    pub async fn acquire(&self) {
+        self.permits
+            .acquire()
+            .await
+            .expect("Rate limiter semaphore closed")
+            .forget();
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FetchParams {
+    pub limit: u16,
+    pub sleep_millis: u64,
+    pub sleep_jitter_millis: u64,
+    pub user_agents: Arc<Vec<String>>,
+    /// `None` - a random filter for every catalog
+    pub catalog_filter: Option<CatalogFilter>,
+    pub challenge_markers: Arc<Vec<String>>,
+    pub json_wait_selector: String,
+    /// Set in `fetch_mode: "http"`, catalog pages are requested without the browser
+    pub http: Option<Arc<HttpFetcher>>,
+    /// Set in `geocode_mode: "http"`, stores are looked up without the browser
+    pub store_http: Option<Arc<HttpFetcher>>,
+    /// Credentials of the current proxy for new browser pages
+    pub proxy_auth: Option<bu::ProxyCredentials>,
+    /// Set with `raw_response_dir`, every JSON body is saved before parsing
+    pub raw_responses: Option<Arc<RawResponseStore>>,
+    pub page_pool: Arc<bu::PagePool>,
+    /// Catalogs with fewer products are rejected as suspicious, 0 disables the check
+    pub min_catalog_products: usize,
+    /// Max share of products without name and price before a catalog is rejected
+    pub max_empty_products_ratio: f64,
+    /// Cap on the whole catalog fetch (all pages), `None` - no cap
+    pub catalog_timeout: Option<Duration>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+/// `None` means a random filter for each request
+fn parse_catalog_filter(name: Option<&str>) -> Option<CatalogFilter> {
+    match name {
+        None | Some("random") => None,
+        Some(name) => {
+            let filter = CatalogFilter::from_name(name);
+            if filter.is_none() {
+                warn!("Unknown catalog_filter {name:?}, using random");
+            }
+            filter
+        }
+    }
+}
+
+impl FetchParams {
+    async fn save_catalog_page<C: std::fmt::Debug>(
+        &self,
+        store_id: &str,
+        c: C,
+        page: CatalogPage,
+        body: &str,
+    ) {
+        if let Some(raw_responses) = &self.raw_responses {
+            raw_responses
+                .save_catalog_page(store_id, &format!("{c:?}"), page.offset, body)
+                .await;
+        }
+    }
+
+    async fn save_store_lookup(&self, body: &str) {
+        if let Some(raw_responses) = &self.raw_responses {
+            raw_responses.save_store_lookup(body).await;
+        }
+    }
+
+    pub fn from_parse_config<R: Retailer>(retailer: &R, pc: &ParseConfig) -> Self {
+        let user_agents = match (&pc.user_agents, pc.user_agent.as_deref()) {
+            (Some(user_agents), _) if !user_agents.is_empty() => user_agents.clone(),
+            (_, Some(user_agent)) => vec![user_agent.to_string()],
+            _ => Vec::new(),
+        };
+        let catalog_filter = parse_catalog_filter(pc.catalog_filter.as_deref());
+        let max_limit = retailer.max_catalog_limit();
+        Self {
+            limit: pc.catalog_limit.unwrap_or(max_limit).clamp(1, max_limit),
+            sleep_millis: pc
+                .sleep_millis_for_each_catalog
+                .unwrap_or(DEFAULT_CATALOG_SLEEP_MILLIS),
+            sleep_jitter_millis: pc.sleep_jitter_millis.unwrap_or(0),
+            user_agents: Arc::new(user_agents),
+            catalog_filter,
+            challenge_markers: Arc::new(pc.challenge_markers.clone().unwrap_or_else(|| {
+                bu::DEFAULT_CHALLENGE_MARKERS
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect()
+            })),
+            json_wait_selector: pc
+                .json_wait_selector
+                .clone()
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or_else(|| "pre".into()),
+            http: None,
+            store_http: None,
+            proxy_auth: None,
+            raw_responses: pc
+                .raw_response_dir
+                .as_deref()
+                .map(|dir| Arc::new(RawResponseStore::new(dir))),
+            page_pool: Arc::default(),
+            min_catalog_products: pc
+                .min_catalog_products
+                .unwrap_or(DEFAULT_MIN_CATALOG_PRODUCTS),
+            max_empty_products_ratio: pc
+                .max_empty_products_ratio
+                .unwrap_or(DEFAULT_MAX_EMPTY_PRODUCTS_RATIO),
+            catalog_timeout: pc.catalog_timeout_secs.map(Duration::from_secs),
+            rate_limiter: pc
+                .max_requests_per_minute
+                .filter(|v| *v > 0)
+                .map(|v| Arc::new(RateLimiter::new(v))),
+        }
+    }
+
+    pub fn catalog_filter(&self) -> CatalogFilter {
+        self.catalog_filter
+            .unwrap_or_else(|| *CATALOG_FILTERS_LIST.choose(&mut rand::rng()).unwrap())
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agents
+            .choose(&mut rand::rng())
+            .map(String::as_str)
+    }
+
+    pub async fn wait_rate_limit(&self) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+    }
+}
+
+/// Requests API endpoints directly with the cookies of the browser session.
+#[derive(Debug)]
+pub struct HttpFetcher {
+    client: reqwest::Client,
+    cookie_header: String,
+}
+
+impl HttpFetcher {
+    pub fn new(cookies: &[Cookie], cookie_domain: &str, proxy: Option<&str>) -> Result<Self> {
+        let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(30));
+        if let Some(proxy) = proxy.map(str::trim).filter(|v| !v.is_empty()) {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        let cookie_header = cookies
+            .iter()
+            .filter(|c| c.domain.trim_start_matches('.').ends_with(cookie_domain))
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        Ok(Self {
+            client: builder.build()?,
+            cookie_header,
+        })
+    }
+
+    /// JSON body of `url`, checked with `bu::ensure_json_body`.
+    pub async fn fetch_json_body(&self, url: &str, user_agent: Option<&str>) -> Result<String> {
+        let response = self
+            .client
+            .get(url)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .header(reqwest::header::COOKIE, &self.cookie_header)
+            .header(
+                reqwest::header::USER_AGENT,
+                user_agent.unwrap_or(bu::DEFAULT_USER_AGENT),
+            )
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Error::HttpStatus {
+                url: url.to_string(),
+                status: status.as_u16(),
+            });
+        }
+        let body = response.text().await?;
+        bu::ensure_json_body(&body, url)?;
+
+        Ok(body)
+    }
+}
+
+async fn http_fetcher(
+    b: &Browser,
+    cookie_domain: &str,
+    proxy: Option<&str>,
+) -> Option<Arc<HttpFetcher>> {
+    let fetcher = match b.get_cookies().await {
+        Ok(cookies) => HttpFetcher::new(&cookies, cookie_domain, proxy),
+        Err(e) => Err(e.into()),
+    };
+    match fetcher {
+        Ok(fetcher) => Some(Arc::new(fetcher)),
+        Err(e) => {
+            error!("Failed to prepare HTTP fetcher, using browser: {e}");
+            None
+        }
+    }
+}
+
+/// Rebuilds the HTTP clients of `fp` from the browser cookies, see `http_mode`/`geocode_mode`.
+async fn set_http_fetchers(
+    b: &Browser,
+    cookie_domain: &str,
+    proxy: Option<&str>,
+    fp: &mut FetchParams,
+    http_mode: bool,
+    geocode_http: bool,
+) {
+    let http = http_fetcher(b, cookie_domain, proxy).await;
+    fp.http = http.clone().filter(|_| http_mode);
+    fp.store_http = http.filter(|_| geocode_http);
+}
+
+async fn fetch_catalog_page<R: Retailer>(
+    retailer: &R,
+    b: &Browser,
+    store_id: &str,
+    c: R::Catalog,
+    catalog_page: CatalogPage,
+    fp: &FetchParams,
+) -> Result<models::CatalogInfo> {
+    let url = retailer.catalog_url(store_id, c, catalog_page);
+    fp.wait_rate_limit().await;
+    let started = Instant::now();
+    if let Some(http) = &fp.http {
+        match http.fetch_json_body(&url, fp.user_agent()).await {
+            Ok(body) => {
+                metrics::catalog_page_fetched(started.elapsed());
+                fp.save_catalog_page(store_id, c, catalog_page, &body).await;
+                return retailer.parse_catalog(&body, c).map_err(|e| {
+                    e.with_context(format!("catalog {c:?} of store {store_id} ({url})"))
+                });
+            }
+            Err(Error::HttpStatus { status: 403, .. }) => {
+                warn!(url = %url, "HTTP 403, falling back to browser");
+                fp.wait_rate_limit().await;
+            }
+            Err(e) => {
+                return Err(e.with_context(format!("catalog {c:?} of store {store_id} ({url})")));
+            }
+        }
+    }
+    let page = bu::open_page_with_retry(
+        b,
+        &bu::OpenPageParams {
+            url: &url,
+            wait: (&fp.json_wait_selector, Duration::from_secs(9)),
+            user_agent: fp.user_agent(),
+            challenge_markers: &fp.challenge_markers,
+            page_pool: Some(&fp.page_pool),
+            proxy_auth: fp.proxy_auth.as_ref(),
+        },
+        OPEN_PAGE_RETRIES,
+        OPEN_PAGE_RETRY_BASE_DELAY,
+    )
+    .await?;
+    let body = bu::extract_json_body(&page, &fp.json_wait_selector).await;
+    fp.page_pool.release(page).await;
+    metrics::catalog_page_fetched(started.elapsed());
+    if let Ok(body) = &body {
+        fp.save_catalog_page(store_id, c, catalog_page, body).await;
+    }
+    body.and_then(|body| retailer.parse_catalog(&body, c))
+        .map_err(|e| e.with_context(format!("catalog {c:?} of store {store_id} ({url})")))
+}
+
+pub async fn fetch_all_catalog_products<R: Retailer>(
+    retailer: &R,
+    b: &Browser,
+    store_id: &str,
+    c: R::Catalog,
+    fp: &FetchParams,
+) -> Result<models::CatalogInfoWithTime> {
+    let mut page = CatalogPage {
+        limit: fp.limit,
+        offset: 0,
+        filter: fp.catalog_filter(),
+    };
+    let catalog = fetch_catalog_page(retailer, b, store_id, c, page, fp).await?;
+    let mut fetched = catalog.products.len();
+    let mut result = models::CatalogInfoWithTime {
+        info: catalog,
+        time: chrono::Utc::now().timestamp(),
+    };
+    let mut seen_ids = result
+        .info
+        .products
+        .iter()
+        .map(|p| p.id.clone())
+        .collect::<HashSet<_>>();
+    while fetched >= fp.limit as usize {
+        tokio::time::sleep(jittered_delay(fp.sleep_millis, fp.sleep_jitter_millis)).await;
+        page.offset += fp.limit as u32;
+        let catalog = fetch_catalog_page(retailer, b, store_id, c, page, fp).await?;
+        fetched = catalog.products.len();
+        let new_products = catalog
+            .products
+            .into_iter()
+            .filter(|p| seen_ids.insert(p.id.clone()))
+            .collect::<Vec<_>>();
+        if new_products.is_empty() {
+            break;
+        }
+        result.info.products.extend(new_products);
+    }
+    fill_catalog_name(retailer, c, &mut result.info);
+    if let Some(reason) = validate_catalog(&result.info, fp) {
+        return Err(Error::SuspiciousResponse {
+            context: format!("catalog {c:?} of store {store_id}"),
+            reason,
+        });
+    }
+
+    Ok(result)
+}
+
+/// `fetch_all_catalog_products` limited by `fp.catalog_timeout`, so a hung page can't stall
+/// the store.
+async fn fetch_catalog_with_timeout<R: Retailer>(
+    retailer: &R,
+    b: &Browser,
+    store_id: &str,
+    c: R::Catalog,
+    fp: &FetchParams,
+) -> Result<models::CatalogInfoWithTime> {
+    let fetch = fetch_all_catalog_products(retailer, b, store_id, c, fp);
+    let Some(catalog_timeout) = fp.catalog_timeout else {
+        return fetch.await;
+    };
+    match tokio::time::timeout(catalog_timeout, fetch).await {
+        Ok(result) => result,
+        Err(e) => Err(Error::from(e).with_context(format!("catalog {c:?} of store {store_id}"))),
+    }
+}
+
+/// Fetches the catalogs selected in `pc` for one store, outside of the store loop (e.g. to
+/// refresh a single store on demand). Failed catalogs are logged and skipped.
+pub async fn parse_store_catalogs<R: Retailer>(
+    retailer: Arc<R>,
+    b: Arc<Browser>,
+    store_id: &str,
+    pc: &ParseConfig,
+) -> Vec<models::CatalogInfoWithTime> {
+    let fp = FetchParams::from_parse_config(retailer.as_ref(), pc);
+    let semaphore = Arc::new(Semaphore::new(
+        pc.max_concurrent_catalogs.unwrap_or(4).max(1),
+    ));
+    let mut join_set = JoinSet::new();
+    for c in retailer.catalogs(pc.catalogs.as_deref()) {
+        let retailer = retailer.clone();
+        let b = b.clone();
+        let store_id = store_id.to_string();
+        let semaphore = semaphore.clone();
+        let fp = fp.clone();
+        join_set.spawn(async move {
+            let _permit = semaphore
+                .acquire_owned()
+                .await
+                .expect("Catalogs semaphore closed");
+            fetch_catalog_with_timeout(retailer.as_ref(), &b, &store_id, c, &fp).await
+        });
+        tokio::time::sleep(jittered_delay(fp.sleep_millis, fp.sleep_jitter_millis)).await;
+    }
+    let mut catalogs = Vec::new();
+    while let Some(r) = join_set.join_next().await {
+        match r {
+            Ok(Ok(catalog)) => catalogs.push(catalog),
+            Ok(Err(e)) => error!(store_id, "Catalog fetch failed: {e}"),
+            Err(e) => error!(store_id, "Catalog task failed: {e}"),
+        }
+    }
+
+    catalogs
+}
+
+fn save_run_stats(stats: &db::RunStats) {
+    if let Err(e) = db::update_run(stats) {
+        error!(run_id = stats.run_id, "Failed to update run: {e}");
+    }
+}
+
+/// Some feeds (e.g. Perekrestok without a title) have no catalog name
+pub(crate) fn fill_catalog_name<R: Retailer>(
+    retailer: &R,
+    c: R::Catalog,
+    catalog: &mut models::CatalogInfo,
+) {
+    if catalog.name.trim().is_empty() {
+        catalog.name = retailer.catalog_name(c).to_string();
+    }
+}
+
+/// Rejects catalogs that deserialized fine but look like an upstream format change:
+/// too few products or mostly default (empty) products.
+pub(crate) fn validate_catalog(catalog: &models::CatalogInfo, fp: &FetchParams) -> Option<String> {
+    let products = catalog.products.len();
+    if products < fp.min_catalog_products {
+        return Some(format!(
+            "{products} products, expected at least {}",
+            fp.min_catalog_products
+        ));
+    }
+    let empty_products = catalog
+        .products
+        .iter()
+        .filter(|p| p.name.trim().is_empty() && p.price <= 0.)
+        .count();
+    if products > 0 && empty_products as f64 / products as f64 > fp.max_empty_products_ratio {
+        return Some(format!(
+            "{empty_products} of {products} products have no name and price"
+        ));
+    }
+
+    None
+}
+
+/// Looks the store up over HTTP (`geocode_mode = "http"`) or in the browser. Opening the lookup
+/// page is retried up to `retries` times with the usual doubling delay; blocked responses and a
+/// missing store are not retried.
+async fn lookup_store<R: Retailer>(
+    retailer: &R,
+    b: &Browser,
+    url: &str,
+    fp: &FetchParams,
+    retries: u32,
+) -> Result<StoreLookup> {
+    fp.wait_rate_limit().await;
+    if let Some(http) = &fp.store_http {
+        let lookup = match http.fetch_json_body(url, fp.user_agent()).await {
+            Ok(body) => {
+                fp.save_store_lookup(&body).await;
+                retailer.parse_store(&body)
+            }
+            Err(e) => Err(e),
+        };
+        match lookup {
+            Ok(store) => return Ok(store),
+            // Nothing to retry in the browser
+            Err(Error::StoreNotFound) => return Err(Error::StoreNotFound),
+            Err(e) => {
+                warn!(url = %url, "HTTP store lookup failed, falling back to browser: {e}");
+                fp.wait_rate_limit().await;
+            }
+        }
+    }
+    let page = bu::open_page_with_retry(
+        b,
+        &OpenPageParams {
+            url,
+            wait: (&fp.json_wait_selector, Duration::from_secs(5)),
+            user_agent: fp.user_agent(),
+            challenge_markers: &fp.challenge_markers,
+            page_pool: Some(&fp.page_pool),
+            proxy_auth: fp.proxy_auth.as_ref(),
+        },
+        retries,
+        OPEN_PAGE_RETRY_BASE_DELAY,
+    )
+    .await?;
+    let body = bu::extract_json_body(&page, &fp.json_wait_selector).await;
+    fp.page_pool.release(page).await;
+    if let Ok(body) = &body {
+        fp.save_store_lookup(body).await;
+    }
+    body.and_then(|body| retailer.parse_store(&body))
+        .map_err(|e| e.with_context(format!("store info ({url})")))
+}
+
+/// `base * 2^(failed_stores - 1)`, capped at `max`
+fn block_backoff(base_secs: u64, max_secs: u64, failed_stores: u32) -> Duration {
+    let factor = 1u64 << failed_stores.saturating_sub(1).min(16);
+    Duration::from_secs(base_secs.saturating_mul(factor).min(max_secs))
+}
+
+/// Distance to the store if it is farther than `max_store_distance_km` from the requested point.
+fn store_too_far(pc: &ParseConfig, lat: f32, lon: f32, store: &StoreLookup) -> Option<f64> {
+    let max_distance_km = pc.max_store_distance_km?;
+    let distance_km = haversine_km(lat as f64, lon as f64, store.lat?, store.lon?);
+
+    Some(distance_km).filter(|v| *v > max_distance_km)
+}
+
+enum StoreTarget {
+    Lookup { lat: f32, lon: f32, url: String },
+    Known(models::StoreInfo),
+}
+
+fn launch_params<'a>(
+    pc: &'a ParseConfig,
+    headless_mode: HeadlessMode,
+    proxy: Option<&'a str>,
+) -> bu::LaunchParams<'a> {
+    bu::LaunchParams {
+        executable: pc.browser_executable.as_deref(),
+        headless_mode,
+        proxy,
+        window_size: pc.window_width.zip(pc.window_height),
+        extra_args: pc.extra_browser_args.as_deref().unwrap_or_default(),
+    }
+}
+
+async fn launch_parse_browser<R: Retailer>(
+    retailer: &R,
+    pc: &ParseConfig,
+    proxy: Option<&str>,
+) -> Result<Browser> {
+    let b = bu::launch_browser(&launch_params(pc, HeadlessMode::True, proxy)).await?;
+    let cookies_store_path = cookies_store_path(retailer, pc);
+    set_cookies_from_path(&b, &cookies_store_path, retailer.cookie_domain()).await?;
+
+    Ok(b)
+}
+
+/// Replaces `b` with a new browser (cookies are applied by `launch_parse_browser`); the old
+/// one is closed once no task holds it.
+async fn relaunch_browser<R: Retailer>(
+    retailer: &R,
+    pc: &ParseConfig,
+    page_pool: &bu::PagePool,
+    b: &mut Arc<Browser>,
+    proxy: Option<&str>,
+) -> Result<()> {
+    let new_b = launch_parse_browser(retailer, pc, proxy).await?;
+    page_pool.drain().await;
+    let old_b = std::mem::replace(b, Arc::new(new_b));
+    if let Ok(mut old_b) = Arc::try_unwrap(old_b) {
+        bu::close_browser(&mut old_b).await;
+    }
+    Ok(())
+}
+
+async fn refresh_cookies<R: Retailer>(
+    retailer: &R,
+    pc: &ParseConfig,
+    b: &Browser,
+    shutdown: &tokio::sync::watch::Receiver<bool>,
+) -> Result<()> {
+    let cookies = update_cookies(retailer, pc, shutdown).await?;
+    set_cookies(b, cookies, retailer.cookie_domain()).await
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ParseConfig {
+    pub browser_executable: Option<String>,
+    pub cookies_store_path: Option<String>,
+    pub pyaterochka_stores_coord_path: Option<String>,
+    pub sleep_millis_for_each_catalog: Option<u64>,
+    pub cookie_refresh_headless: Option<bool>,
+    pub catalog_limit: Option<u16>,
+    pub proxy: Option<String>,
+    pub proxies: Option<Vec<String>>,
+    pub max_concurrent_catalogs: Option<usize>,
+    pub insert_into_db: Option<bool>,
+    pub sleep_jitter_millis: Option<u64>,
+    pub min_store_revisit_secs: Option<u64>,
+    pub catalogs: Option<Vec<String>>,
+    pub dry_run: Option<bool>,
+    pub cookie_refresh_threshold: Option<u32>,
+    pub user_agent: Option<String>,
+    pub user_agents: Option<Vec<String>>,
+    pub store_source: Option<String>,
+    pub catalog_filter: Option<String>,
+    pub cookie_wait_timeout_secs: Option<u64>,
+    pub challenge_markers: Option<Vec<String>>,
+    pub run_once: Option<bool>,
+    pub max_loops: Option<u64>,
+    pub max_runtime_secs: Option<u64>,
+    pub download_images: Option<bool>,
+    pub image_store_path: Option<String>,
+    pub max_store_distance_km: Option<f64>,
+    pub status_port: Option<u16>,
+    pub metrics_port: Option<u16>,
+    pub json_wait_selector: Option<String>,
+    pub fetch_mode: Option<String>,
+    pub vacuum_every_loops: Option<u64>,
+    pub history_retention_days: Option<u64>,
+    pub min_catalog_products: Option<usize>,
+    pub max_empty_products_ratio: Option<f64>,
+    pub window_width: Option<u32>,
+    pub window_height: Option<u32>,
+    pub extra_browser_args: Option<Vec<String>>,
+    pub catalog_timeout_secs: Option<u64>,
+    pub shuffle_stores: Option<bool>,
+    pub resume: Option<bool>,
+    pub max_requests_per_minute: Option<u32>,
+    pub cookie_min_validity_secs: Option<u64>,
+    /// Config file re-read on SIGHUP, see `apply_reloaded_config`
+    pub config_path: Option<String>,
+    pub json_output_dir: Option<String>,
+    pub compress_output: Option<bool>,
+    pub block_backoff_base_secs: Option<u64>,
+    pub block_backoff_max_secs: Option<u64>,
+    pub cookie_wait_selector: Option<String>,
+    pub cookie_page_timeout_secs: Option<u64>,
+    pub geocode_mode: Option<String>,
+    pub store_lookup_retries: Option<u32>,
+    pub raw_response_dir: Option<String>,
+    pub restart_browser_every_stores: Option<usize>,
+    pub status_bind_address: Option<String>,
+    pub metrics_bind_address: Option<String>,
+}
+
+impl ParseConfig {
+    pub fn builder() -> ParseConfigBuilder {
+        ParseConfigBuilder::default()
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ParseConfigBuilder {
+    config: ParseConfig,
+}
+
+impl ParseConfigBuilder {
+    pub fn browser_executable(mut self, v: impl Into<String>) -> Self {
+        self.config.browser_executable = Some(v.into());
+        self
+    }
+
+    pub fn cookies_store_path(mut self, v: impl Into<String>) -> Self {
+        self.config.cookies_store_path = Some(v.into());
+        self
+    }
+
+    pub fn pyaterochka_stores_coord_path(mut self, v: impl Into<String>) -> Self {
+        self.config.pyaterochka_stores_coord_path = Some(v.into());
+        self
+    }
+
+    pub fn sleep_millis_for_each_catalog(mut self, v: u64) -> Self {
+        self.config.sleep_millis_for_each_catalog = Some(v);
+        self
+    }
+
+    pub fn cookie_refresh_headless(mut self, v: bool) -> Self {
+        self.config.cookie_refresh_headless = Some(v);
+        self
+    }
+
+    pub fn catalog_limit(mut self, v: u16) -> Self {
+        self.config.catalog_limit = Some(v);
+        self
+    }
+
+    pub fn proxy(mut self, v: impl Into<String>) -> Self {
+        self.config.proxy = Some(v.into());
+        self
+    }
+
+    pub fn proxies<I, S>(mut self, v: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.proxies = Some(v.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn max_concurrent_catalogs(mut self, v: usize) -> Self {
+        self.config.max_concurrent_catalogs = Some(v);
+        self
+    }
+
+    pub fn insert_into_db(mut self, v: bool) -> Self {
+        self.config.insert_into_db = Some(v);
+        self
+    }
+
+    pub fn sleep_jitter_millis(mut self, v: u64) -> Self {
+        self.config.sleep_jitter_millis = Some(v);
+        self
+    }
+
+    pub fn min_store_revisit_secs(mut self, v: u64) -> Self {
+        self.config.min_store_revisit_secs = Some(v);
+        self
+    }
+
+    pub fn catalogs<I, S>(mut self, v: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.catalogs = Some(v.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn dry_run(mut self, v: bool) -> Self {
+        self.config.dry_run = Some(v);
+        self
+    }
+
+    pub fn cookie_refresh_threshold(mut self, v: u32) -> Self {
+        self.config.cookie_refresh_threshold = Some(v);
+        self
+    }
+
+    pub fn user_agent(mut self, v: impl Into<String>) -> Self {
+        self.config.user_agent = Some(v.into());
+        self
+    }
+
+    pub fn user_agents<I, S>(mut self, v: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.user_agents = Some(v.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn store_source(mut self, v: impl Into<String>) -> Self {
+        self.config.store_source = Some(v.into());
+        self
+    }
+
+    pub fn catalog_filter(mut self, v: impl Into<String>) -> Self {
+        self.config.catalog_filter = Some(v.into());
+        self
+    }
+
+    pub fn cookie_wait_timeout_secs(mut self, v: u64) -> Self {
+        self.config.cookie_wait_timeout_secs = Some(v);
+        self
+    }
+
+    pub fn challenge_markers<I, S>(mut self, v: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.challenge_markers = Some(v.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn run_once(mut self, v: bool) -> Self {
+        self.config.run_once = Some(v);
+        self
+    }
+
+    pub fn max_loops(mut self, v: u64) -> Self {
+        self.config.max_loops = Some(v);
+        self
+    }
+
+    pub fn max_runtime_secs(mut self, v: u64) -> Self {
+        self.config.max_runtime_secs = Some(v);
+        self
+    }
+
+    pub fn download_images(mut self, v: bool) -> Self {
+        self.config.download_images = Some(v);
+        self
+    }
+
+    pub fn image_store_path(mut self, v: impl Into<String>) -> Self {
+        self.config.image_store_path = Some(v.into());
+        self
+    }
+
+    pub fn max_store_distance_km(mut self, v: f64) -> Self {
+        self.config.max_store_distance_km = Some(v);
+        self
+    }
+
+    pub fn status_port(mut self, v: u16) -> Self {
+        self.config.status_port = Some(v);
+        self
+    }
+
+    pub fn metrics_port(mut self, v: u16) -> Self {
+        self.config.metrics_port = Some(v);
+        self
+    }
+
+    pub fn json_wait_selector(mut self, v: impl Into<String>) -> Self {
+        self.config.json_wait_selector = Some(v.into());
+        self
+    }
+
+    pub fn fetch_mode(mut self, v: impl Into<String>) -> Self {
+        self.config.fetch_mode = Some(v.into());
+        self
+    }
+
+    pub fn vacuum_every_loops(mut self, v: u64) -> Self {
+        self.config.vacuum_every_loops = Some(v);
+        self
+    }
+
+    pub fn history_retention_days(mut self, v: u64) -> Self {
+        self.config.history_retention_days = Some(v);
+        self
+    }
+
+    pub fn min_catalog_products(mut self, v: usize) -> Self {
+        self.config.min_catalog_products = Some(v);
+        self
+    }
+
+    pub fn max_empty_products_ratio(mut self, v: f64) -> Self {
+        self.config.max_empty_products_ratio = Some(v);
+        self
+    }
+
+    pub fn window_width(mut self, v: u32) -> Self {
+        self.config.window_width = Some(v);
+        self
+    }
+
+    pub fn window_height(mut self, v: u32) -> Self {
+        self.config.window_height = Some(v);
+        self
+    }
+
+    pub fn extra_browser_args<I, S>(mut self, v: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.extra_browser_args = Some(v.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn catalog_timeout_secs(mut self, v: u64) -> Self {
+        self.config.catalog_timeout_secs = Some(v);
+        self
+    }
+
+    pub fn shuffle_stores(mut self, v: bool) -> Self {
+        self.config.shuffle_stores = Some(v);
+        self
+    }
+
+    pub fn resume(mut self, v: bool) -> Self {
+        self.config.resume = Some(v);
+        self
+    }
+
+    pub fn max_requests_per_minute(mut self, v: u32) -> Self {
+        self.config.max_requests_per_minute = Some(v);
+        self
+    }
+
+    pub fn cookie_min_validity_secs(mut self, v: u64) -> Self {
+        self.config.cookie_min_validity_secs = Some(v);
+        self
+    }
+
+    pub fn config_path(mut self, v: impl Into<String>) -> Self {
+        self.config.config_path = Some(v.into());
+        self
+    }
+
+    pub fn json_output_dir(mut self, v: impl Into<String>) -> Self {
+        self.config.json_output_dir = Some(v.into());
+        self
+    }
+
+    pub fn compress_output(mut self, v: bool) -> Self {
+        self.config.compress_output = Some(v);
+        self
+    }
+
+    pub fn block_backoff_base_secs(mut self, v: u64) -> Self {
+        self.config.block_backoff_base_secs = Some(v);
+        self
+    }
+
+    pub fn block_backoff_max_secs(mut self, v: u64) -> Self {
+        self.config.block_backoff_max_secs = Some(v);
+        self
+    }
+
+    pub fn cookie_wait_selector(mut self, v: impl Into<String>) -> Self {
+        self.config.cookie_wait_selector = Some(v.into());
+        self
+    }
+
+    pub fn cookie_page_timeout_secs(mut self, v: u64) -> Self {
+        self.config.cookie_page_timeout_secs = Some(v);
+        self
+    }
+
+    pub fn geocode_mode(mut self, v: impl Into<String>) -> Self {
+        self.config.geocode_mode = Some(v.into());
+        self
+    }
+
+    pub fn store_lookup_retries(mut self, v: u32) -> Self {
+        self.config.store_lookup_retries = Some(v);
+        self
+    }
+
+    pub fn raw_response_dir(mut self, v: impl Into<String>) -> Self {
+        self.config.raw_response_dir = Some(v.into());
+        self
+    }
+
+    pub fn restart_browser_every_stores(mut self, v: usize) -> Self {
+        self.config.restart_browser_every_stores = Some(v);
+        self
+    }
+
+    pub fn status_bind_address(mut self, v: impl Into<String>) -> Self {
+        self.config.status_bind_address = Some(v.into());
+        self
+    }
+
+    pub fn metrics_bind_address(mut self, v: impl Into<String>) -> Self {
+        self.config.metrics_bind_address = Some(v.into());
+        self
+    }
+
+    pub fn build(self) -> ParseConfig {
+        self.config
+    }
+}
+
+#[cfg(feature = "status-server")]
+fn spawn_status_server(
+    port: Option<u16>,
+    bind_address: Option<&str>,
+    status: &Arc<RunStatus>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let port = port?;
+    let bind_address = bind_address.unwrap_or(crate::status::DEFAULT_STATUS_BIND_ADDRESS);
+    let address = match bind_address.trim().parse::<std::net::IpAddr>() {
+        Ok(ip) => std::net::SocketAddr::new(ip, port),
+        Err(e) => {
+            error!("Invalid status_bind_address {bind_address:?}: {e}");
+            return None;
+        }
+    };
+    let status = status.clone();
+    Some(tokio::spawn(async move {
+        info!("Status server listening on {address}");
+        if let Err(e) = crate::status::serve(address, status).await {
+            error!("Status server failed: {e}");
+        }
+    }))
+}
+
+#[cfg(not(feature = "status-server"))]
+fn spawn_status_server(
+    port: Option<u16>,
+    _bind_address: Option<&str>,
+    _status: &Arc<RunStatus>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    if port.is_some() {
+        warn!("status_port is set, but x5parser was built without the status-server feature");
+    }
+    None
+}
+
+struct RunContext<R> {
+    retailer: Arc<R>,
+    deadline: Option<Instant>,
+    status: Arc<RunStatus>,
+    page_pool: Arc<bu::PagePool>,
+    /// Set on the first Ctrl+C: the current store is finished with the catalogs fetched so far
+    shutdown: tokio::sync::watch::Receiver<bool>,
+    /// Config re-read on SIGHUP, taken before the next store
+    reload: Arc<Mutex<Option<Config>>>,
+}
+
+impl<R> RunContext<R> {
+    fn shutdown_requested(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+}
+
+async fn read_config(path: &str) -> Result<Config> {
+    let config_json = tokio::fs::read_to_string(path).await?;
+    let mut config = serde_json::from_str::<Config>(&config_json)?;
+    config.apply_env();
+    Ok(config)
+}
+
+#[cfg(unix)]
+fn spawn_reload_handler(config_path: Option<&str>, reload: &Arc<Mutex<Option<Config>>>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let Some(path) = config_path.map(str::to_string) else {
+        return;
+    };
+    let reload = reload.clone();
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Failed to listen for SIGHUP: {e}");
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading {path}");
+            match read_config(&path).await {
+                Ok(config) => *reload.lock().unwrap() = Some(config),
+                Err(e) => error!("Failed to reload config {path}: {e}"),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_reload_handler(_config_path: Option<&str>, _reload: &Arc<Mutex<Option<Config>>>) {}
+
+/// Applies the timing and catalog fields of a reloaded config, the rest is only read at start.
+fn apply_reloaded_config<R: Retailer>(
+    retailer: &R,
+    pc: &ParseConfig,
+    config: &Config,
+    fp: &mut FetchParams,
+    catalogs_list: &mut Vec<R::Catalog>,
+) {
+    fp.sleep_millis = config
+        .sleep_millis_for_each_catalog
+        .unwrap_or(DEFAULT_CATALOG_SLEEP_MILLIS);
+    fp.sleep_jitter_millis = config.sleep_jitter_millis.unwrap_or(0);
+    fp.catalog_filter = parse_catalog_filter(config.catalog_filter.as_deref());
+    *catalogs_list = retailer.catalogs(config.catalogs.as_deref());
+    info!(
+        "Config reloaded: sleep {}ms, jitter {}ms, {} catalogs",
+        fp.sleep_millis,
+        fp.sleep_jitter_millis,
+        catalogs_list.len()
+    );
+    let ignored = [
+        (
+            "browser_executable",
+            config.browser_executable != pc.browser_executable,
+        ),
+        (
+            "cookies_store_path",
+            config.cookies_store_path != pc.cookies_store_path,
+        ),
+        ("proxy", config.proxy != pc.proxy),
+        ("proxies", config.proxies != pc.proxies),
+        ("fetch_mode", config.fetch_mode != pc.fetch_mode),
+        ("geocode_mode", config.geocode_mode != pc.geocode_mode),
+        ("store_source", config.store_source != pc.store_source),
+    ]
+    .into_iter()
+    .filter_map(|(name, changed)| changed.then_some(name))
+    .collect::<Vec<_>>();
+    if !ignored.is_empty() {
+        warn!(
+            "Changes of {} are ignored until restart",
+            ignored.join(", ")
+        );
+    }
+}
+
+pub async fn start_parsing<R: Retailer>(retailer: R, pc: &ParseConfig) -> Result<()> {
+    start_parsing_with(retailer, pc, |_, _| {}).await
+}
+
+pub async fn start_parsing_with<R, F>(retailer: R, pc: &ParseConfig, mut on_store: F) -> Result<()>
+where
+    R: Retailer,
+    F: FnMut(&models::StoreInfo, &[models::CatalogInfoWithTime]),
+{
+    // Tables of the retailer, unless the prefix was already set explicitly
+    db::init_table_prefix(Some(retailer.name()));
+    let (shutdown_tx, shutdown) = tokio::sync::watch::channel(false);
+    let run = RunContext {
+        retailer: Arc::new(retailer),
+        deadline: pc
+            .max_runtime_secs
+            .map(|v| Instant::now() + Duration::from_secs(v)),
+        status: Arc::new(RunStatus::new()),
+        page_pool: Arc::new(bu::PagePool::new(
+            pc.max_concurrent_catalogs.unwrap_or(4).max(1),
+        )),
+        shutdown,
+        reload: Arc::default(),
+    };
+    spawn_reload_handler(pc.config_path.as_deref(), &run.reload);
+    let status_server = spawn_status_server(
+        pc.status_port,
+        pc.status_bind_address.as_deref(),
+        &run.status,
+    );
+    metrics::install_exporter(pc.metrics_port, pc.metrics_bind_address.as_deref());
+    ensure_fresh_cookies(run.retailer.as_ref(), pc, &run.shutdown).await?;
+    let mut proxy_pool = bu::ProxyPool::new(pc.proxies.clone().unwrap_or_default());
+    let initial_proxy = proxy_pool.next_proxy().or(pc.proxy.as_deref());
+    let mut b = Arc::new(launch_parse_browser(run.retailer.as_ref(), pc, initial_proxy).await?);
+    let (tx, mut rx) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C");
+        info!("Ctrl+C received, saving the current store (press Ctrl+C again to exit now)...");
+        let _ = shutdown_tx.send(true);
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C");
+        info!("Ctrl+C received again, exiting without saving");
+        let _ = tx.send(());
+    });
+    let mut join_set = JoinSet::new();
+    let parse = parse_stores(
+        pc,
+        &run,
+        &mut b,
+        &mut proxy_pool,
+        &mut join_set,
+        &mut on_store,
+    );
+    let result = tokio::select! {
+        r = parse => r,
+        Ok(()) = &mut rx => Ok(()),
+    };
+    join_set.shutdown().await;
+    run.page_pool.drain().await;
+    if let Some(status_server) = status_server {
+        status_server.abort();
+    }
+    if let Ok(mut b) = Arc::try_unwrap(b) {
+        bu::close_browser(&mut b).await;
+    }
+
+    result
+}
+
+async fn parse_stores<R, F>(
+    pc: &ParseConfig,
+    run: &RunContext<R>,
+    b: &mut Arc<Browser>,
+    proxy_pool: &mut bu::ProxyPool,
+    join_set: &mut JoinSet<Result<models::CatalogInfoWithTime>>,
+    on_store: &mut F,
+) -> Result<()>
+where
+    R: Retailer,
+    F: FnMut(&models::StoreInfo, &[models::CatalogInfoWithTime]),
+{
+    let retailer = run.retailer.as_ref();
+    let shuffle_stores = pc.shuffle_stores.unwrap_or(true);
+    let resume = pc.resume.unwrap_or(false) && !pc.dry_run.unwrap_or(false);
+    if resume && shuffle_stores {
+        warn!("resume requires shuffle_stores: false, starting from the first store");
+    }
+    let resume = resume && !shuffle_stores;
+    let mut resume_from = if resume {
+        db::get_state(RESUME_CURSOR_KEY)?
+            .and_then(|v| v.parse::<usize>().ok())
+            .map_or(0, |v| v + 1)
+    } else {
+        0
+    };
+    if resume_from > 0 {
+        info!("Resuming from store #{resume_from}");
+    }
+    let mut store_targets = match pc.store_source.as_deref().unwrap_or("coords") {
+        "db" => db::pyaterochka_stores()?
+            .into_iter()
+            .map(StoreTarget::Known)
+            .collect::<Vec<_>>(),
+        source => {
+            if source != "coords" {
+                warn!("Unknown store_source {source:?}, using coords");
+            }
+            let coords_path = pc
+                .pyaterochka_stores_coord_path
+                .clone()
+                .unwrap_or_else(|| format!("{}_stores_coord.json", retailer.name()));
+            read_store_coords(&coords_path, shuffle_stores)
+                .await?
+                .into_iter()
+                .map(|[lat, lon]| StoreTarget::Lookup {
+                    lat,
+                    lon,
+                    url: retailer.store_lookup_url(lat, lon),
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+    let mut fp = FetchParams::from_parse_config(retailer, pc);
+    fp.page_pool = run.page_pool.clone();
+    let http_mode = match pc.fetch_mode.as_deref() {
+        None | Some("browser") => false,
+        Some("http") => true,
+        Some(mode) => {
+            warn!("Unknown fetch_mode {mode:?}, using browser");
+            false
+        }
+    };
+    let geocode_http = match pc.geocode_mode.as_deref() {
+        None | Some("browser") => false,
+        Some("http") => true,
+        Some(mode) => {
+            warn!("Unknown geocode_mode {mode:?}, using browser");
+            false
+        }
+    };
+    let needs_http = http_mode || geocode_http;
+    let mut rebuild_http = needs_http;
+    let image_downloader = if pc.download_images.unwrap_or(false) {
+        Some(ImageDownloader::new(
+            pc.image_store_path.as_deref(),
+            pc.user_agent.as_deref(),
+        )?)
+    } else {
+        None
+    };
+    let json_output = pc
+        .json_output_dir
+        .as_deref()
+        .map(|dir| JsonOutput::new(dir, pc.compress_output.unwrap_or(false)));
+    let mut catalogs_list = retailer.catalogs(pc.catalogs.as_deref());
+    let max_concurrent_catalogs = pc.max_concurrent_catalogs.unwrap_or(4).max(1);
+    let catalogs_semaphore = Arc::new(Semaphore::new(max_concurrent_catalogs));
+    let mut rng = rand::rng();
+    let cookie_refresh_threshold = pc.cookie_refresh_threshold.unwrap_or(5).max(1);
+    let lookup_retries = pc.store_lookup_retries.unwrap_or(2);
+    let mut blocked_responses = 0u32;
+    // Stores in a row where every catalog failed
+    let mut failed_stores = 0u32;
+    let mut proxy_rotated = true;
+    // Stores parsed by the current browser, see `restart_browser_every_stores`
+    let mut stores_since_launch = 0usize;
+    let mut loops = 0u64;
+    loop {
+        let mut stores_set = HashSet::new();
+        let mut run_stats = if pc.dry_run.unwrap_or(false) || !pc.insert_into_db.unwrap_or(true) {
+            None
+        } else {
+            db::start_run()
+                .inspect_err(|e| error!("Failed to record run: {e}"))
+                .ok()
+        };
+        if shuffle_stores {
+            store_targets.shuffle(&mut rng);
+        }
+        for (sn, target) in store_targets.iter().enumerate() {
+            if let Some(deadline) = run.deadline
+                && Instant::now() >= deadline
+            {
+                info!("max_runtime_secs reached, stopping");
+                return Ok(());
+            }
+            if run.shutdown_requested() {
+                return Ok(());
+            }
+            let reloaded = run.reload.lock().unwrap().take();
+            if let Some(config) = reloaded {
+                apply_reloaded_config(retailer, pc, &config, &mut fp, &mut catalogs_list);
+            }
+            if sn < resume_from {
+                continue;
+            }
+            // With a proxy pool the browser is already relaunched for every parsed store
+            if let Some(restart_every) = pc.restart_browser_every_stores.filter(|v| *v > 0)
+                && proxy_pool.is_empty()
+                && stores_since_launch >= restart_every
+            {
+                info!("{stores_since_launch} stores parsed, restarting browser");
+                let proxy = proxy_pool.current().or(pc.proxy.as_deref());
+                match relaunch_browser(retailer, pc, &run.page_pool, b, proxy).await {
+                    Ok(()) => rebuild_http = needs_http,
+                    Err(e) => error!("Failed to restart browser: {e}"),
+                }
+                stores_since_launch = 0;
+            }
+            if blocked_responses >= cookie_refresh_threshold {
+                warn!("{blocked_responses} blocked responses in a row, refreshing cookies");
+                if let Err(e) = refresh_cookies(retailer, pc, b, &run.shutdown).await {
+                    error!("Failed to refresh cookies: {e}");
+                }
+                blocked_responses = 0;
+                rebuild_http = needs_http;
+            }
+            let proxy = proxy_pool.current().or(pc.proxy.as_deref());
+            fp.proxy_auth = proxy.and_then(|v| bu::split_proxy_credentials(v).1);
+            if rebuild_http {
+                set_http_fetchers(
+                    b,
+                    retailer.cookie_domain(),
+                    proxy,
+                    &mut fp,
+                    http_mode,
+                    geocode_http,
+                )
+                .await;
+                rebuild_http = false;
+            }
+            let _ = bu::cleanup_browser_pages(b, &run.page_pool.page_ids()).await;
+            let mut store_location = None;
+            let store_info = match target {
+                StoreTarget::Lookup { lat, lon, url } => {
+                    if let Ok(Some(store_info)) = db::cached_coord_store(*lat, *lon) {
+                        Arc::new(store_info)
+                    } else {
+                        match lookup_store(retailer, b, url, &fp, lookup_retries).await {
+                            Ok(store) => {
+                                blocked_responses = 0;
+                                if let Some(distance_km) = store_too_far(pc, *lat, *lon, &store) {
+                                    info!(
+                                        url = %url,
+                                        store_id = %store.info.id,
+                                        "Skip store: {distance_km:.1} km from requested point"
+                                    );
+                                    continue;
+                                }
+                                store_location = store.lat.zip(store.lon);
+                                let store_info = store.info;
+                                if !pc.dry_run.unwrap_or(false)
+                                    && let Err(e) =
+                                        db::cache_coord_store(*lat, *lon, &store_info.id)
+                                {
+                                    warn!(url = %url, "Failed to cache store lookup: {e}");
+                                }
+                                Arc::new(store_info)
+                            }
+                            Err(e) => {
+                                if e.is_blocked() {
+                                    blocked_responses += 1;
+                                }
+                                warn!(
+                                    url = %url,
+                                    "Skip coordinate {lat},{lon}: store lookup failed: {e}"
+                                );
+                                if let Some(run_stats) = &mut run_stats {
+                                    run_stats.errors += 1;
+                                }
+                                let delay = jittered_delay(500, fp.sleep_jitter_millis);
+                                sleep_unless_shutdown(delay, &run.shutdown).await;
+                                continue;
+                            }
+                        }
+                    }
+                }
+                StoreTarget::Known(store_info) => Arc::new(store_info.clone()),
+            };
+            if !stores_set.insert(store_info.id.clone()) {
+                continue;
+            }
+            if let Some(min_revisit_secs) = pc.min_store_revisit_secs
+                && let Ok(Some(parsed_at)) = db::store_last_parsed(&store_info.id)
+            {
+                let elapsed = chrono::Utc::now().timestamp() - parsed_at;
+                if elapsed < min_revisit_secs as i64 {
+                    info!(store_id = %store_info.id, "Skip store: parsed {elapsed}s ago");
+                    continue;
+                }
+            }
+            // The lookup above went through the previous store's proxy: rotate only for a store
+            // that is about to be parsed, not for skipped targets or failed lookups
+            if !proxy_pool.is_empty() && !proxy_rotated {
+                let proxy = proxy_pool.next_proxy();
+                match relaunch_browser(retailer, pc, &run.page_pool, b, proxy).await {
+                    Ok(()) => {
+                        stores_since_launch = 0;
+                        fp.proxy_auth = proxy.and_then(|v| bu::split_proxy_credentials(v).1);
+                        if needs_http {
+                            let domain = retailer.cookie_domain();
+                            set_http_fetchers(b, domain, proxy, &mut fp, http_mode, geocode_http)
+                                .await;
+                        }
+                    }
+                    Err(e) => error!("Failed to relaunch browser with next proxy: {e}"),
+                }
+                proxy_rotated = true;
+            }
+            let store_span = info_span!("store", store_id = %store_info.id);
+            info!(parent: &store_span, "{sn}. {} - {:?}", store_info.address, store_info.city);
+            for (cn, &c) in catalogs_list.iter().enumerate() {
+                if run.shutdown_requested() {
+                    break;
+                }
+                {
+                    let retailer = run.retailer.clone();
+                    let b = b.clone();
+                    let store_info = store_info.clone();
+                    let catalogs_semaphore = catalogs_semaphore.clone();
+                    let fp = fp.clone();
+                    let catalog_span = store_span.clone();
+                    join_set.spawn(
+                        async move {
+                            let _permit = catalogs_semaphore
+                                .acquire_owned()
+                                .await
+                                .expect("Catalogs semaphore closed");
+                            let result = fetch_catalog_with_timeout(
+                                retailer.as_ref(),
+                                &b,
+                                &store_info.id,
+                                c,
+                                &fp,
+                            )
+                            .await?;
+                            info!(
+                                "{cn}. {} {}",
+                                retailer.catalog_name(c),
+                                result.info.products.len()
+                            );
+                            Result::Ok(result)
+                        }
+                        .instrument(catalog_span),
+                    );
+                }
+                let delay = jittered_delay(fp.sleep_millis, fp.sleep_jitter_millis);
+                sleep_unless_shutdown(delay, &run.shutdown).await;
+            }
+            let mut catalogs = Vec::new();
+            let mut shutdown = run.shutdown.clone();
+            let mut interrupted = false;
+            let mut catalog_errors = 0u64;
+            loop {
+                let r = tokio::select! {
+                    r = join_set.join_next() => r,
+                    _ = shutdown.wait_for(|v| *v), if !interrupted => {
+                        info!(parent: &store_span, "Shutdown requested, saving fetched catalogs");
+                        interrupted = true;
+                        join_set.abort_all();
+                        continue;
+                    }
+                };
+                let Some(r) = r else {
+                    break;
+                };
+                if matches!(&r, Err(e) if e.is_cancelled()) {
+                    continue;
+                }
+                metrics::catalog_fetched(matches!(r, Ok(Ok(_))));
+                match r {
+                    Ok(Ok(catalog)) => {
+                        blocked_responses = 0;
+                        catalogs.push(catalog);
+                    }
+                    Ok(Err(e)) => {
+                        if e.is_blocked() {
+                            blocked_responses += 1;
+                        }
+                        error!(parent: &store_span, "Catalog fetch failed: {e}");
+                        catalog_errors += 1;
+                    }
+                    Err(e) => {
+                        error!(parent: &store_span, "Catalog task failed: {e}");
+                        catalog_errors += 1;
+                    }
+                }
+            }
+            run.status.record_store();
+            metrics::store_parsed();
+            on_store(&store_info, &catalogs);
+            stores_since_launch += 1;
+            proxy_rotated = false;
+            if let Some(json_output) = &json_output
+                && !pc.dry_run.unwrap_or(false)
+            {
+                match json_output.write_store(&store_info, &catalogs).await {
+                    Ok(path) => info!(parent: &store_span, "Saved {}", path.display()),
+                    Err(e) => error!(parent: &store_span, "Failed to save store JSON: {e}"),
+                }
+            }
+            if pc.dry_run.unwrap_or(false) {
+                let products = catalogs
+                    .iter()
+                    .map(|c| c.info.products.len())
+                    .sum::<usize>();
+                info!(
+                    parent: &store_span,
+                    "Dry run: would insert {products} products from {} catalogs",
+                    catalogs.len()
+                );
+            } else if pc.insert_into_db.unwrap_or(true) {
+                let insert_stats = db::pyaterochka_insert_data(&store_info, &catalogs)?;
+                info!(
+                    parent: &store_span,
+                    "Upserted {} products, {} new prices",
+                    insert_stats.products_upserted,
+                    insert_stats.price_rows_inserted
+                );
+                if let Some((lat, lon)) = store_location
+                    && let Err(e) = db::set_store_location(&store_info.id, lat, lon)
+                {
+                    warn!(parent: &store_span, "Failed to save store location: {e}");
+                }
+                let products = catalogs
+                    .iter()
+                    .map(|c| c.info.products.len())
+                    .sum::<usize>();
+                metrics::products_inserted(products);
+                if let Some(run_stats) = &mut run_stats {
+                    run_stats.stores_parsed += 1;
+                    run_stats.products_inserted += products as u64;
+                    run_stats.price_rows_inserted += insert_stats.price_rows_inserted as u64;
+                    run_stats.errors += catalog_errors;
+                    save_run_stats(run_stats);
+                }
+                if resume && let Err(e) = db::set_state(RESUME_CURSOR_KEY, &sn.to_string()) {
+                    warn!(parent: &store_span, "Failed to save resume cursor: {e}");
+                }
+                if let Some(image_downloader) = &image_downloader
+                    && !interrupted
+                {
+                    let paths = image_downloader
+                        .download_catalogs(&catalogs)
+                        .instrument(store_span.clone())
+                        .await;
+                    if let Err(e) = db::set_product_image_paths(&paths) {
+                        warn!(parent: &store_span, "Failed to save image paths: {e}");
+                    }
+                }
+            }
+            if interrupted {
+                info!(parent: &store_span, "Saved {} catalogs, stopping", catalogs.len());
+                return Ok(());
+            }
+            if let Some(base_secs) = pc.block_backoff_base_secs.filter(|v| *v > 0) {
+                if !catalogs.is_empty() || catalog_errors == 0 {
+                    failed_stores = 0;
+                } else {
+                    failed_stores += 1;
+                    let max_secs = pc.block_backoff_max_secs.unwrap_or(600).max(base_secs);
+                    let delay = block_backoff(base_secs, max_secs, failed_stores);
+                    if delay.as_secs() >= max_secs {
+                        // Refresh cookies before the next store
+                        blocked_responses = blocked_responses.max(cookie_refresh_threshold);
+                    }
+                    warn!(
+                        parent: &store_span,
+                        "All catalogs failed ({failed_stores} stores in a row), sleeping {delay:?}"
+                    );
+                    sleep_unless_shutdown(delay, &run.shutdown).await;
+                }
+            }
+        }
+        loops += 1;
+        run.status.record_loop();
+        resume_from = 0;
+        if resume && let Err(e) = db::clear_state(RESUME_CURSOR_KEY) {
+            warn!("Failed to clear resume cursor: {e}");
+        }
+        if let Some(run_stats) = &mut run_stats {
+            run_stats.finished_at = Some(chrono::Utc::now().timestamp());
+            save_run_stats(run_stats);
+        }
+        if let Some(retention_days) = pc.history_retention_days
+            && !pc.dry_run.unwrap_or(false)
+        {
+            match db::prune_price_history(retention_days as i64 * 24 * 60 * 60) {
+                Ok(deleted) => info!("Pruned {deleted} price history rows"),
+                Err(e) => error!("Failed to prune price history: {e}"),
+            }
+        }
+        if let Some(vacuum_every_loops) = pc.vacuum_every_loops.filter(|v| *v > 0)
+            && loops % vacuum_every_loops == 0
+            && !pc.dry_run.unwrap_or(false)
+        {
+            info!("Running VACUUM");
+            if let Err(e) = db::vacuum() {
+                error!("VACUUM failed: {e}");
+            }
+        }
+        if pc.run_once.unwrap_or(false) {
+            info!("All stores parsed once, stopping");
+            return Ok(());
+        }
+        if let Some(max_loops) = pc.max_loops
+            && loops >= max_loops
+        {
+            info!("max_loops ({max_loops}) reached, stopping");
+            return Ok(());
+        }
+    }
+}
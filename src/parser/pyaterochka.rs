@@ -1,14 +1,12 @@
-use crate::browser_utils::{self as bu, OpenPageParams};
-use crate::db;
 use crate::error::Result;
 use crate::parser::models::pyaterochka as models;
-use chromiumoxide::cdp::browser_protocol::network::Cookie;
-use chromiumoxide::{Browser, browser::HeadlessMode};
-use rand::seq::{IndexedRandom, SliceRandom};
-use std::collections::HashSet;
+use crate::parser::pipeline::{self, FetchParams, ParseConfig};
+use crate::parser::retailer::{CatalogPage, Retailer, StoreLookup};
+use chromiumoxide::Browser;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::task::JoinSet;
+use tracing::warn;
+
+pub use crate::parser::retailer::CatalogFilter;
 
 pub const MAX_CATALOG_API_LIMIT: u16 = 499;
 
@@ -53,13 +51,6 @@ pub enum Catalog {
     DlyaDomaIDachi,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum CatalogFilter {
-    Default,
-    PriceDesc,
-    PriceAsc,
-}
-
 impl CatalogFilter {
     pub fn as_url_query(&self) -> &'static str {
         match self {
@@ -70,12 +61,6 @@ impl CatalogFilter {
     }
 }
 
-const CATALOG_FILTERS_LIST: [CatalogFilter; 3] = [
-    CatalogFilter::Default,
-    CatalogFilter::PriceDesc,
-    CatalogFilter::PriceAsc,
-];
-
 impl Catalog {
     pub fn as_catalog_id(&self) -> &'static str {
         match self {
@@ -99,229 +84,211 @@ impl Catalog {
         }
     }
 
-    pub fn as_api_url(&self, store_id: &str, limit: u16) -> String {
-        let mut rng = rand::rng();
-        let filter = CATALOG_FILTERS_LIST
-            .choose(&mut rng)
-            .unwrap()
-            .as_url_query();
+    /// Название категории на сайте
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Catalog::GotovayaEda => "Готовая еда",
+            Catalog::OvoshchiFruktyOrekhi => "Овощи, фрукты, орехи",
+            Catalog::MolochnayaProduktsiyaIYaytso => "Молочная продукция и яйцо",
+            Catalog::KhlebIVypechka => "Хлеб и выпечка",
+            Catalog::MyasoPtitsaKolbasy => "Мясо, птица, колбасы",
+            Catalog::RybaIMoreprodukty => "Рыба и морепродукты",
+            Catalog::Sladosti => "Сладости",
+            Catalog::SnekiIChipsy => "Снеки и чипсы",
+            Catalog::Bakaleya => "Бакалея",
+            Catalog::ZamorozhennyeProdukty => "Замороженные продукты",
+            Catalog::VodaINapitki => "Вода и напитки",
+            Catalog::ZdorovyyVybor => "Здоровый выбор",
+            Catalog::DlyaDetey => "Для детей",
+            Catalog::DlyaZhivotnykh => "Для животных",
+            Catalog::KrasotaGigienaApteka => "Красота, гигиена, аптека",
+            Catalog::StirkaIUborka => "Стирка и уборка",
+            Catalog::DlyaDomaIDachi => "Для дома и дачи",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Catalog> {
+        match id {
+            "251C12884" => Some(Catalog::GotovayaEda),
+            "251C12886" => Some(Catalog::OvoshchiFruktyOrekhi),
+            "251C12887" => Some(Catalog::MolochnayaProduktsiyaIYaytso),
+            "251C12888" => Some(Catalog::KhlebIVypechka),
+            "251C12889" => Some(Catalog::MyasoPtitsaKolbasy),
+            "251C12890" => Some(Catalog::RybaIMoreprodukty),
+            "251C12900" => Some(Catalog::Sladosti),
+            "251C12901" => Some(Catalog::SnekiIChipsy),
+            "251C12902" => Some(Catalog::Bakaleya),
+            "251C12903" => Some(Catalog::ZamorozhennyeProdukty),
+            "251C12904" => Some(Catalog::VodaINapitki),
+            "251C12905" => Some(Catalog::ZdorovyyVybor),
+            "251C12906" => Some(Catalog::DlyaDetey),
+            "251C12907" => Some(Catalog::DlyaZhivotnykh),
+            "251C12908" => Some(Catalog::KrasotaGigienaApteka),
+            "251C12909" => Some(Catalog::StirkaIUborka),
+            "251C12910" => Some(Catalog::DlyaDomaIDachi),
+            _ => None,
+        }
+    }
+
+    pub fn all() -> impl Iterator<Item = Catalog> {
+        MAIN_CATALOG_LIST.into_iter()
+    }
+
+    pub fn from_name(name: &str) -> Option<Catalog> {
+        Catalog::all().find(|c| format!("{c:?}").eq_ignore_ascii_case(name))
+    }
+
+    pub fn from_id_or_name(v: &str) -> Option<Catalog> {
+        Catalog::from_id(v).or_else(|| Catalog::from_name(v))
+    }
+
+    pub fn as_api_url(
+        &self,
+        store_id: &str,
+        limit: u16,
+        offset: u32,
+        filter: CatalogFilter,
+    ) -> String {
         format!(
-            "https://5d.5ka.ru/api/catalog/v2/stores/{store_id}/categories/{catalog_id}/products?mode=delivery&include_restrict=true&limit={limit}{filter}",
-            catalog_id = self.as_catalog_id()
+            "https://5d.5ka.ru/api/catalog/v2/stores/{store_id}/categories/{catalog_id}/products?mode=delivery&include_restrict=true&limit={limit}&offset={offset}{filter}",
+            catalog_id = self.as_catalog_id(),
+            filter = filter.as_url_query()
         )
     }
 }
 
+pub fn selected_catalogs(catalogs: Option<&[String]>) -> Vec<Catalog> {
+    let Some(catalogs) = catalogs else {
+        return Catalog::all().collect();
+    };
+    catalogs
+        .iter()
+        .filter_map(|v| {
+            let catalog = Catalog::from_id_or_name(v.trim());
+            if catalog.is_none() {
+                warn!("Unknown catalog {v:?}, skipping");
+            }
+            catalog
+        })
+        .collect()
+}
+
 pub fn store_from_coord_url(lat: f32, lon: f32) -> String {
     format!("https://5d.5ka.ru/api/orders/v1/orders/stores/?lat={lat}&lon={lon}")
 }
 
 pub const HOME_PAGE_URL: &str = "https://5ka.ru/";
 
-pub async fn read_pyaterochka_coords(path: Option<&str>) -> Result<Vec<[f32; 2]>> {
-    let coords_data =
-        tokio::fs::read_to_string(path.unwrap_or("pyaterochka_stores_coord.json")).await?;
-    let mut pyaterochka_stores_coord = serde_json::from_str::<Vec<[f32; 2]>>(&coords_data)?;
-    let mut rng = rand::rng();
-    pyaterochka_stores_coord.shuffle(&mut rng);
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Pyaterochka;
 
-    Ok(pyaterochka_stores_coord)
-}
+impl Retailer for Pyaterochka {
+    type Catalog = Catalog;
 
-async fn set_cookies_from_path(b: &Browser, path: &str) -> Result<()> {
-    if !std::fs::exists(path).unwrap_or(false) {
-        return Ok(());
+    fn name(&self) -> &'static str {
+        "pyaterochka"
     }
-    let cookies_json = tokio::fs::read_to_string(path).await?;
-    let cookies_param = serde_json::from_str::<Vec<Cookie>>(&cookies_json)?
-        .into_iter()
-        .map(bu::cookie_into_param)
-        .collect::<Vec<_>>();
-    if !cookies_param.is_empty() {
-        b.set_cookies(cookies_param).await?;
+
+    fn home_page_url(&self) -> &'static str {
+        HOME_PAGE_URL
     }
-    Ok(())
-}
 
-async fn pyaterochka_update_cookies_with_borwser(
-    b: &Browser,
-    cookies_store_path: Option<&str>,
-) -> Result<Vec<Cookie>> {
-    let page = bu::open_page(
-        &b,
-        &bu::OpenPageParams {
-            url: HOME_PAGE_URL,
-            ..Default::default()
-        },
-    )
-    .await?;
-
-    tokio::time::sleep(Duration::from_secs(5)).await;
-
-    while let Some(url) = page.url().await? {
-        if url.as_str() == HOME_PAGE_URL {
-            break;
-        }
-        tokio::time::sleep(Duration::from_secs(1)).await;
+    fn cookie_domain(&self) -> &'static str {
+        "5ka.ru"
     }
 
-    let cookies = b.get_cookies().await?;
-    let cookies_json = serde_json::ser::to_string_pretty(&cookies)?;
-    tokio::fs::write(
-        cookies_store_path.unwrap_or("pyaterochka_cookies"),
-        cookies_json,
-    )
-    .await?;
+    fn max_catalog_limit(&self) -> u16 {
+        MAX_CATALOG_API_LIMIT
+    }
 
-    let _ = page.close().await;
+    fn catalog_id(&self, c: Catalog) -> &'static str {
+        c.as_catalog_id()
+    }
 
-    Ok(cookies)
-}
+    fn catalog_name(&self, c: Catalog) -> &'static str {
+        c.display_name()
+    }
+
+    fn catalogs(&self, selected: Option<&[String]>) -> Vec<Catalog> {
+        selected_catalogs(selected)
+    }
+
+    fn store_lookup_url(&self, lat: f32, lon: f32) -> String {
+        store_from_coord_url(lat, lon)
+    }
 
-async fn pyaterochka_update_cookies(
-    executable: Option<&str>,
-    cookies_store_path: Option<&str>,
-) -> Result<Vec<Cookie>> {
-    let mut b = bu::launch_browser(executable, HeadlessMode::False).await?;
+    fn parse_store(&self, body: &str) -> Result<StoreLookup> {
+        let store_api_info = serde_json::from_str::<models::StoreApiInfo>(body)?;
+        let (lat, lon) = (store_api_info.lat, store_api_info.lon);
 
-    if let Some(path) = cookies_store_path {
-        set_cookies_from_path(&b, path).await?;
+        Ok(StoreLookup {
+            info: store_api_info.into(),
+            lat,
+            lon,
+        })
     }
 
-    let cookies = pyaterochka_update_cookies_with_borwser(&b, cookies_store_path).await?;
+    fn catalog_url(&self, store_id: &str, c: Catalog, page: CatalogPage) -> String {
+        c.as_api_url(store_id, page.limit, page.offset, page.filter)
+    }
 
-    bu::close_browser(&mut b).await;
+    fn parse_catalog(&self, body: &str, c: Catalog) -> Result<models::CatalogInfo> {
+        let catalog = serde_json::from_str::<models::Catalog>(body)?;
 
-    Ok(cookies)
+        Ok(models::CatalogInfo::from_catalog_with_id(
+            catalog,
+            c.as_catalog_id().into(),
+        ))
+    }
 }
 
-#[derive(Debug, Default)]
-pub struct ParseConfig<'a> {
-    pub browser_executable: Option<&'a str>,
-    pub cookies_store_path: Option<&'a str>,
-    pub pyaterochka_stores_coord_path: Option<&'a str>,
-    pub sleep_millis_for_each_catalog: Option<u64>,
+pub async fn read_pyaterochka_coords(path: Option<&str>) -> Result<Vec<[f32; 2]>> {
+    pipeline::read_store_coords(path.unwrap_or("pyaterochka_stores_coord.json"), true).await
 }
 
-pub async fn start_parsing<'a>(pc: &ParseConfig<'a>) -> Result<()> {
-    pyaterochka_update_cookies(pc.browser_executable, pc.cookies_store_path).await?;
-    let b = Arc::new(bu::launch_browser(pc.browser_executable, HeadlessMode::True).await?);
-    let (tx, mut rx) = tokio::sync::oneshot::channel::<()>();
-    {
-        let b = b.clone();
-        tokio::spawn(async move {
-            tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
-            println!("\nCtrl+C received, initiating graceful shutdown...");
-            let browser_ref = unsafe { &mut *(Arc::<Browser>::as_ptr(&b) as *mut Browser) };
-            bu::close_browser(browser_ref).await;
-            let _ = tx.send(());
-        });
-    }
-    if let Some(cookies_store_path) = pc.cookies_store_path {
-        set_cookies_from_path(&b, cookies_store_path).await?;
-    }
-    let stores_coords = read_pyaterochka_coords(pc.pyaterochka_stores_coord_path).await?;
-    let mut store_by_coord_urls = stores_coords
-        .into_iter()
-        .map(|v| store_from_coord_url(v[0], v[1]))
-        .collect::<Vec<_>>();
-    let mut rng = rand::rng();
-    loop {
-        let mut stores_set = HashSet::new();
-        store_by_coord_urls.shuffle(&mut rng);
-        for (sn, s) in store_by_coord_urls.iter().enumerate() {
-            let _ = bu::cleanup_browser_pages(&b).await;
-            let page = bu::open_page(
-                &b,
-                &OpenPageParams {
-                    url: s,
-                    wait: ("pre", Duration::from_secs(5)),
-                },
-            )
-            .await;
-            if page.is_err() {
-                if rx.try_recv().is_ok() {
-                    return Ok(());
-                }
-                eprintln!("Not found store info content block");
-                if rx.try_recv().is_ok() {
-                    return Ok(());
-                }
-                tokio::time::sleep(Duration::from_millis(500)).await;
-                continue;
-            }
-            let page = unsafe { page.unwrap_unchecked() };
-
-            let find_element = page.find_element("pre").await;
-            let content = find_element
-                .unwrap()
-                .inner_text()
-                .await?
-                .unwrap_or_default();
-            let store_api_info = serde_json::from_str::<models::StoreApiInfo>(&content);
-            if let Err(ref e) = store_api_info {
-                eprintln!("StoreApiInfo from str: {:?}", e)
-            }
-            if store_api_info.is_err() {
-                eprintln!("Not found store info content");
-                tokio::time::sleep(Duration::from_millis(500)).await;
-                continue;
-            }
-            let store_api_info = unsafe { store_api_info.unwrap_unchecked() };
-            let store_info = Arc::new(Into::<models::StoreInfo>::into(store_api_info));
-            let _ = page.close().await;
-            if !stores_set.insert(store_info.id.clone()) {
-                continue;
-            }
-            println!(
-                "---------------------------------------\n{sn}. {} - {:?}\n---------------------------------------",
-                store_info.address, store_info.city
-            );
-            let mut join_set = JoinSet::new();
-            for (cn, c) in MAIN_CATALOG_LIST.iter().enumerate() {
-                {
-                    let b = b.clone();
-                    let store_info = store_info.clone();
-                    join_set.spawn(async move {
-                        let url = c.as_api_url(&store_info.id, MAX_CATALOG_API_LIMIT);
-                        let page = bu::open_page(
-                            &b,
-                            &bu::OpenPageParams {
-                                url: url.as_str(),
-                                wait: ("pre", Duration::from_secs(9)),
-                            },
-                        )
-                        .await?;
-                        let find_element = page.find_element("pre").await?;
-                        let content = find_element.inner_text().await?.unwrap_or_default();
-                        let catalog = serde_json::from_str::<models::Catalog>(&content)?;
-                        let result = models::CatalogInfoWithTime::from_catalog_with_id(
-                            catalog,
-                            c.as_catalog_id().into(),
-                            None,
-                        );
-                        let _ = page.close().await;
-                        println!("{cn}. {:?} {}", c, result.info.products.len());
-                        Result::Ok(result)
-                    });
-                }
-                tokio::time::sleep(
-                    Duration::from_millis(pc.sleep_millis_for_each_catalog.unwrap_or(700))
-                ).await;
-            }
-            let catalogs = join_set
-                .join_all()
-                .await
-                .into_iter()
-                .inspect(|r| {
-                    if r.is_err() {
-                        eprintln!("Some error while parse catalog page");
-                    }
-                })
-                .filter(Result::is_ok)
-                .map(Result::unwrap)
-                .collect::<Vec<_>>();
-            db::pyaterochka_insert_data(&store_info, &catalogs)?;
+pub async fn fetch_all_catalog_products(
+    b: &Browser,
+    store_id: &str,
+    c: Catalog,
+    fp: &FetchParams,
+) -> Result<models::CatalogInfoWithTime> {
+    pipeline::fetch_all_catalog_products(&Pyaterochka, b, store_id, c, fp).await
+}
+
+/// All catalogs selected in `pc` for one store, see `pipeline::parse_store_catalogs`.
+pub async fn parse_store_catalogs(
+    b: Arc<Browser>,
+    store_id: &str,
+    pc: &ParseConfig,
+) -> Vec<models::CatalogInfoWithTime> {
+    pipeline::parse_store_catalogs(Arc::new(Pyaterochka), b, store_id, pc).await
+}
+
+pub async fn start_parsing(pc: &ParseConfig) -> Result<()> {
+    pipeline::start_parsing(Pyaterochka, pc).await
+}
+
+pub async fn start_parsing_with<F>(pc: &ParseConfig, on_store: F) -> Result<()>
+where
+    F: FnMut(&models::StoreInfo, &[models::CatalogInfoWithTime]),
+{
+    pipeline::start_parsing_with(Pyaterochka, pc, on_store).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_id_or_name_round_trips_every_catalog() {
+        assert_eq!(Catalog::all().count(), 17);
+        for c in Catalog::all() {
+            assert_eq!(Catalog::from_id_or_name(c.as_catalog_id()), Some(c));
+            let name = format!("{c:?}");
+            assert_eq!(Catalog::from_id_or_name(&name), Some(c));
+            assert_eq!(Catalog::from_id_or_name(&name.to_lowercase()), Some(c));
         }
+        assert_eq!(Catalog::from_id_or_name("251C00000"), None);
+        assert_eq!(Catalog::from_id_or_name(""), None);
     }
 }
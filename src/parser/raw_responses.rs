@@ -0,0 +1,244 @@
+use crate::config::Config;
+use crate::db;
+use crate::error::Result;
+use crate::parser::models::pyaterochka::{CatalogInfo, CatalogInfoWithTime, StoreInfo};
+use crate::parser::pipeline::{
+    DEFAULT_MAX_EMPTY_PRODUCTS_RATIO, DEFAULT_MIN_CATALOG_PRODUCTS, FetchParams, fill_catalog_name,
+    validate_catalog,
+};
+use crate::parser::retailer::Retailer;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+/// Raw JSON bodies saved before parsing, for debugging and `replay`:
+/// `stores/{millis}.json` for store lookups and
+/// `catalogs/{store_id}/{catalog}_{offset}_{millis}.json` for catalog pages.
+#[derive(Debug)]
+pub struct RawResponseStore {
+    dir: PathBuf,
+}
+
+fn file_name_part(v: &str) -> String {
+    v.replace(|c: char| !c.is_alphanumeric() && c != '-', "_")
+}
+
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+impl RawResponseStore {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    pub async fn save_store_lookup(&self, body: &str) {
+        let path = self
+            .dir
+            .join("stores")
+            .join(format!("{}.json", now_millis()));
+        save(&path, body).await;
+    }
+
+    /// `catalog` is the `Debug` name of the catalog, resolved back by `Retailer::catalogs`.
+    pub async fn save_catalog_page(&self, store_id: &str, catalog: &str, offset: u32, body: &str) {
+        let path = self
+            .dir
+            .join("catalogs")
+            .join(file_name_part(store_id))
+            .join(format!(
+                "{}_{offset}_{}.json",
+                file_name_part(catalog),
+                now_millis()
+            ));
+        save(&path, body).await;
+    }
+}
+
+/// Failures are only logged: a debug dump must not break parsing.
+async fn save(path: &Path, body: &str) {
+    let result = async {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, body).await
+    }
+    .await;
+    if let Err(e) = result {
+        warn!("Failed to save raw response {}: {e}", path.display());
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReplayStats {
+    pub stores: usize,
+    pub catalog_pages: usize,
+    /// Files that failed to read or parse, each is logged with its path
+    pub failed_files: usize,
+    /// Catalogs rejected by the same checks as during parsing (`min_catalog_products`, ...)
+    pub rejected_catalogs: usize,
+}
+
+async fn json_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !tokio::fs::try_exists(dir).await? {
+        return Ok(files);
+    }
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|v| v == "json") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// `(catalog, millis)` from `{catalog}_{offset}_{millis}.json`
+fn parse_page_file_name(path: &Path) -> Option<(&str, i64)> {
+    let stem = path.file_stem()?.to_str()?;
+    let (rest, millis) = stem.rsplit_once('_')?;
+    let (catalog, _offset) = rest.rsplit_once('_')?;
+
+    Some((catalog, millis.parse().ok()?))
+}
+
+/// Unreadable files are logged and counted, the replay goes on.
+async fn read_file(path: &Path, stats: &mut ReplayStats) -> Option<String> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(body) => Some(body),
+        Err(e) => {
+            error!("{}: {e}", path.display());
+            stats.failed_files += 1;
+            None
+        }
+    }
+}
+
+/// Re-runs parsing (and inserting, unless `insert` is false) over the bodies saved in `dir`
+/// without a browser. Stores missing from the saved lookups are taken from the DB.
+/// Catalogs are checked with the thresholds of `config`, like during parsing.
+pub async fn replay<R: Retailer>(
+    retailer: &R,
+    dir: &str,
+    insert: bool,
+    config: &Config,
+) -> Result<ReplayStats> {
+    let dir = Path::new(dir);
+    let mut stats = ReplayStats::default();
+    let fp = FetchParams {
+        min_catalog_products: config
+            .min_catalog_products
+            .unwrap_or(DEFAULT_MIN_CATALOG_PRODUCTS),
+        max_empty_products_ratio: config
+            .max_empty_products_ratio
+            .unwrap_or(DEFAULT_MAX_EMPTY_PRODUCTS_RATIO),
+        ..Default::default()
+    };
+    let mut stores = db::pyaterochka_stores()?
+        .into_iter()
+        .map(|v| (v.id.clone(), v))
+        .collect::<HashMap<_, _>>();
+    for path in json_files(&dir.join("stores")).await? {
+        let Some(body) = read_file(&path, &mut stats).await else {
+            continue;
+        };
+        match retailer.parse_store(&body) {
+            Ok(store) => {
+                stores.insert(store.info.id.clone(), store.info);
+            }
+            Err(e) => {
+                error!("{}: {e}", path.display());
+                stats.failed_files += 1;
+            }
+        }
+    }
+
+    let catalogs_dir = dir.join("catalogs");
+    if !tokio::fs::try_exists(&catalogs_dir).await? {
+        return Ok(stats);
+    }
+    let mut store_dirs = tokio::fs::read_dir(&catalogs_dir).await?;
+    while let Some(entry) = store_dirs.next_entry().await? {
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let store_id = entry.file_name().to_string_lossy().into_owned();
+        // Pages by catalog, checked as a whole like `fetch_all_catalog_products` does
+        let mut pages = BTreeMap::<String, Vec<CatalogInfoWithTime>>::new();
+        for path in json_files(&entry.path()).await? {
+            let Some((name, millis)) = parse_page_file_name(&path) else {
+                warn!("Skipping {}: unexpected file name", path.display());
+                continue;
+            };
+            let Some(&c) = retailer.catalogs(Some(&[name.to_string()])).first() else {
+                stats.failed_files += 1;
+                continue;
+            };
+            let Some(body) = read_file(&path, &mut stats).await else {
+                continue;
+            };
+            match retailer.parse_catalog(&body, c) {
+                Ok(mut info) => {
+                    fill_catalog_name(retailer, c, &mut info);
+                    pages
+                        .entry(name.to_string())
+                        .or_default()
+                        .push(CatalogInfoWithTime {
+                            info,
+                            time: millis / 1000,
+                        });
+                }
+                Err(e) => {
+                    error!("{}: {e}", path.display());
+                    stats.failed_files += 1;
+                }
+            }
+        }
+        let mut catalogs = Vec::new();
+        for (name, pages) in pages {
+            let whole = CatalogInfo {
+                id: pages[0].info.id.clone(),
+                name: pages[0].info.name.clone(),
+                brand_list: Vec::new(),
+                products: pages
+                    .iter()
+                    .flat_map(|p| p.info.products.iter().cloned())
+                    .collect(),
+            };
+            if let Some(reason) = validate_catalog(&whole, &fp) {
+                error!("Store {store_id}: catalog {name} rejected: {reason}");
+                stats.rejected_catalogs += 1;
+                continue;
+            }
+            catalogs.extend(pages);
+        }
+        if catalogs.is_empty() {
+            continue;
+        }
+        catalogs.sort_by_key(|c| c.time);
+        let store_info = stores.get(&store_id).cloned().unwrap_or_else(|| StoreInfo {
+            id: store_id.clone(),
+            ..Default::default()
+        });
+        let products = catalogs
+            .iter()
+            .map(|c| c.info.products.len())
+            .sum::<usize>();
+        info!(
+            "Store {store_id}: {} catalog pages, {products} products",
+            catalogs.len()
+        );
+        if insert {
+            db::pyaterochka_insert_data(&store_info, &catalogs)?;
+        }
+        stats.stores += 1;
+        stats.catalog_pages += catalogs.len();
+    }
+
+    Ok(stats)
+}
@@ -0,0 +1,79 @@
+use crate::error::Result;
+use crate::parser::models::pyaterochka::{CatalogInfo, StoreInfo};
+use std::fmt::Debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CatalogFilter {
+    Default,
+    PriceDesc,
+    PriceAsc,
+}
+
+impl CatalogFilter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "default" => Some(Self::Default),
+            "price_desc" => Some(Self::PriceDesc),
+            "price_asc" => Some(Self::PriceAsc),
+            _ => None,
+        }
+    }
+}
+
+pub const CATALOG_FILTERS_LIST: [CatalogFilter; 3] = [
+    CatalogFilter::Default,
+    CatalogFilter::PriceDesc,
+    CatalogFilter::PriceAsc,
+];
+
+/// One page of a catalog request.
+#[derive(Debug, Clone, Copy)]
+pub struct CatalogPage {
+    pub limit: u16,
+    pub offset: u32,
+    pub filter: CatalogFilter,
+}
+
+/// Store found by coordinates; `lat`/`lon` are the store location if the API returns it.
+#[derive(Debug, Default, Clone)]
+pub struct StoreLookup {
+    pub info: StoreInfo,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+}
+
+/// Retail chain specific part of the parsing pipeline: URLs of the API and mapping of its
+/// responses into the shared models. Everything else (browser, cookies, proxies, pagination,
+/// DB) is handled by `parser::pipeline`.
+pub trait Retailer: Send + Sync + 'static {
+    type Catalog: Copy + Debug + Send + Sync + 'static;
+
+    /// Short name, used in logs and default file names
+    fn name(&self) -> &'static str;
+
+    /// Page opened to obtain cookies
+    fn home_page_url(&self) -> &'static str;
+
+    /// Cookies of this domain are sent with direct HTTP requests
+    fn cookie_domain(&self) -> &'static str;
+
+    /// Max page size accepted by the catalog API
+    fn max_catalog_limit(&self) -> u16;
+
+    /// Id of the catalog in the API
+    fn catalog_id(&self, c: Self::Catalog) -> &'static str;
+
+    /// Human-readable catalog name for logs and listings
+    fn catalog_name(&self, c: Self::Catalog) -> &'static str;
+
+    /// Catalogs selected by id or name, all catalogs if `selected` is `None`
+    fn catalogs(&self, selected: Option<&[String]>) -> Vec<Self::Catalog>;
+
+    fn store_lookup_url(&self, lat: f32, lon: f32) -> String;
+
+    fn parse_store(&self, body: &str) -> Result<StoreLookup>;
+
+    fn catalog_url(&self, store_id: &str, c: Self::Catalog, page: CatalogPage) -> String;
+
+    fn parse_catalog(&self, body: &str, c: Self::Catalog) -> Result<CatalogInfo>;
+}
@@ -0,0 +1,82 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+/// Progress counters of a running `start_parsing`, shared with the status server.
+#[derive(Debug, Default)]
+pub struct RunStatus {
+    started_at: AtomicI64,
+    last_store_at: AtomicI64,
+    stores_parsed: AtomicU64,
+    loops: AtomicU64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct StatusSnapshot {
+    pub started_at: i64,
+    pub last_store_at: Option<i64>,
+    pub stores_parsed: u64,
+    pub loops: u64,
+    /// Latest row of the runs table, filled by the status server
+    pub run: Option<crate::db::RunStats>,
+}
+
+impl RunStatus {
+    pub fn new() -> Self {
+        let status = Self::default();
+        status
+            .started_at
+            .store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+        status
+    }
+
+    pub fn record_store(&self) {
+        self.last_store_at
+            .store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+        self.stores_parsed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_loop(&self) {
+        self.loops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatusSnapshot {
+        let last_store_at = self.last_store_at.load(Ordering::Relaxed);
+        StatusSnapshot {
+            started_at: self.started_at.load(Ordering::Relaxed),
+            last_store_at: Some(last_store_at).filter(|v| *v > 0),
+            stores_parsed: self.stores_parsed.load(Ordering::Relaxed),
+            loops: self.loops.load(Ordering::Relaxed),
+            run: None,
+        }
+    }
+}
+
+/// Only local clients can reach the status server unless `status_bind_address` is set.
+pub const DEFAULT_STATUS_BIND_ADDRESS: &str = "127.0.0.1";
+
+/// Minimal HTTP server answering every request with the status snapshot as JSON.
+#[cfg(feature = "status-server")]
+pub async fn serve(
+    address: std::net::SocketAddr,
+    status: std::sync::Arc<RunStatus>,
+) -> crate::error::Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut snapshot = status.snapshot();
+        snapshot.run = crate::db::current_run_stats().ok().flatten();
+        let body = serde_json::to_string(&snapshot)?;
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}